@@ -0,0 +1,58 @@
+use std::env;
+
+use object_store::azure::{AzureConfigKey, MicrosoftAzureBuilder};
+
+use crate::store::client_retry_config;
+
+/// Where the credentials used to authorize Azure requests come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AzureCredentials {
+    /// `AZURE_STORAGE_ACCOUNT_KEY`, `AZURE_STORAGE_SAS_KEY`, `AZURE_CLIENT_ID` and the other
+    /// `AZURE_*` variables, falling back to managed identity when none are set.
+    #[default]
+    Environment,
+    /// A shared access signature, such as `sv=2022-11-02&ss=b&srt=co&sp=rl&sig=...`.
+    SasToken(String),
+}
+
+/// Configuration of every Azure Blob Storage store.
+#[derive(Debug, Clone, Default)]
+pub struct AzureOptions {
+    /// storage account. Taken from `AZURE_STORAGE_ACCOUNT_NAME` or the URL if none provided
+    pub account: Option<String>,
+    /// read from a local Azurite emulator, at `AZURITE_BLOB_STORAGE_URL` or
+    /// `http://127.0.0.1:10000`, with its well-known account and key unless others are set
+    pub use_emulator: Option<bool>,
+    pub credentials: AzureCredentials,
+}
+
+impl AzureOptions {
+    pub fn use_emulator(&self) -> bool {
+        self.use_emulator.unwrap_or(false)
+    }
+
+    /// builder of an Azure store for a `az://<container>/<path>` or
+    /// `abfs://<container>@<account>.dfs.core.windows.net/<path>` URL, with these options applied
+    pub fn builder(&self, url: &str) -> MicrosoftAzureBuilder {
+        let mut builder = match &self.credentials {
+            AzureCredentials::Environment => MicrosoftAzureBuilder::from_env(),
+            // other credentials of the environment would take precedence over the token
+            AzureCredentials::SasToken(sas_token) => {
+                let builder =
+                    MicrosoftAzureBuilder::new().with_config(AzureConfigKey::SasKey, sas_token);
+                match env::var("AZURE_STORAGE_ACCOUNT_NAME") {
+                    Ok(account) => builder.with_account(account),
+                    Err(_) => builder,
+                }
+            }
+        };
+
+        if let Some(account) = &self.account {
+            builder = builder.with_account(account);
+        }
+        if self.use_emulator() {
+            builder = builder.with_use_emulator(true);
+        }
+        builder.with_url(url).with_retry(client_retry_config())
+    }
+}
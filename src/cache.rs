@@ -0,0 +1,421 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::SystemTime};
+
+use alloy_primitives::hex;
+use bytes::Bytes;
+use object_store::ObjectMeta;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// default upper bound of the size of the cached files, 10 GiB
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+
+const DATA_EXTENSION: &str = "data";
+const ENTRY_EXTENSION: &str = "json";
+const PARTIAL_EXTENSION: &str = "partial";
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid cache entry: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Options of a [`BundleCache`].
+#[derive(Debug, Clone, Default)]
+pub struct CacheOptions {
+    /// upper bound of the size of the cached files, in bytes. The least recently used files are
+    /// evicted to stay under it
+    pub max_size: Option<u64>,
+}
+
+impl CacheOptions {
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(DEFAULT_CACHE_MAX_SIZE)
+    }
+}
+
+/// What is known of the object a cached file was downloaded from, stored next to the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    store_url: String,
+    path: String,
+    size: usize,
+    e_tag: Option<String>,
+}
+
+impl CacheEntry {
+    /// whether the object is still the one that was cached
+    fn matches(&self, meta: &ObjectMeta) -> bool {
+        self.size == meta.size && (meta.e_tag.is_none() || self.e_tag == meta.e_tag)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    /// size and last use of every cached file, by key
+    files: HashMap<String, (u64, SystemTime)>,
+    total_size: u64,
+}
+
+/// Local directory holding copies of the files read from stores.
+///
+/// Files are keyed by store URL and object path, so one cache can be shared by several stores.
+/// A cached copy is only used while the size and ETag of the object in the store still match.
+#[derive(Debug)]
+pub struct BundleCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+}
+
+impl BundleCache {
+    /// opens the cache in `dir`, creating the directory if needed and indexing the files already in
+    /// it. Files left incomplete by an interrupted write or removal are deleted
+    pub fn open(dir: impl Into<PathBuf>, options: CacheOptions) -> Result<Self, CacheError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut index = CacheIndex::default();
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let path = dir_entry?.path();
+            match path.extension().and_then(|extension| extension.to_str()) {
+                // left behind by an interrupted write
+                Some(PARTIAL_EXTENSION) => std::fs::remove_file(&path)?,
+                Some(DATA_EXTENSION) if path.with_extension(ENTRY_EXTENSION).exists() => {
+                    let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                        continue;
+                    };
+                    let metadata = std::fs::metadata(&path)?;
+                    index
+                        .files
+                        .insert(key.to_string(), (metadata.len(), metadata.modified()?));
+                    index.total_size += metadata.len();
+                }
+                // the other half of the file was removed, or never renamed into place
+                Some(DATA_EXTENSION) => std::fs::remove_file(&path)?,
+                Some(ENTRY_EXTENSION) if !path.with_extension(DATA_EXTENSION).exists() => {
+                    std::fs::remove_file(&path)?
+                }
+                _ => {}
+            }
+        }
+
+        let cache = BundleCache {
+            dir,
+            max_size: options.max_size(),
+            index: Mutex::new(index),
+        };
+        // the size limit may be lower than in the previous run
+        for key in cache.evict(None) {
+            cache.remove_files(&key);
+        }
+        Ok(cache)
+    }
+
+    /// the cached copy of an object, if there is one and it is still the object described by `meta`
+    pub async fn get(
+        &self,
+        store_url: &str,
+        path: &str,
+        meta: &ObjectMeta,
+    ) -> Result<Option<Bytes>, CacheError> {
+        let key = cache_key(store_url, path);
+        if !self.index.lock().unwrap().files.contains_key(&key) {
+            return Ok(None);
+        }
+
+        let entry: CacheEntry =
+            serde_json::from_slice(&tokio::fs::read(self.entry_path(&key)).await?)?;
+        if !entry.matches(meta) {
+            log::debug!("Cached copy of {} in {} is stale", path, store_url);
+            self.remove(&key);
+            return Ok(None);
+        }
+
+        let bytes = tokio::fs::read(self.data_path(&key)).await?;
+        if bytes.len() != entry.size {
+            log::debug!("Cached copy of {} in {} is truncated", path, store_url);
+            self.remove(&key);
+            return Ok(None);
+        }
+
+        self.touch(&key);
+        Ok(Some(bytes.into()))
+    }
+
+    /// caches a copy of an object, evicting the least recently used files if the cache gets too large
+    pub async fn put(
+        &self,
+        store_url: &str,
+        path: &str,
+        meta: &ObjectMeta,
+        bytes: &Bytes,
+    ) -> Result<(), CacheError> {
+        let size = bytes.len() as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+
+        let key = cache_key(store_url, path);
+        let entry = CacheEntry {
+            store_url: store_url.to_string(),
+            path: path.to_string(),
+            size: bytes.len(),
+            e_tag: meta.e_tag.clone(),
+        };
+
+        // both files are written to partial files and renamed into place, the entry last, so a
+        // file is only indexed once it is complete. Concurrent writes of the same object each use
+        // their own partial files
+        let partial = format!("{}.{:016x}", key, rand::random::<u64>());
+        let partial_data_path = self.dir.join(format!(
+            "{}.{}.{}",
+            partial, DATA_EXTENSION, PARTIAL_EXTENSION
+        ));
+        let partial_entry_path = self.dir.join(format!(
+            "{}.{}.{}",
+            partial, ENTRY_EXTENSION, PARTIAL_EXTENSION
+        ));
+        tokio::fs::write(&partial_data_path, bytes).await?;
+        tokio::fs::write(&partial_entry_path, serde_json::to_vec(&entry)?).await?;
+        tokio::fs::rename(&partial_data_path, self.data_path(&key)).await?;
+        tokio::fs::rename(&partial_entry_path, self.entry_path(&key)).await?;
+
+        {
+            let mut index = self.index.lock().unwrap();
+            if let Some((previous_size, _)) =
+                index.files.insert(key.clone(), (size, SystemTime::now()))
+            {
+                index.total_size -= previous_size;
+            }
+            index.total_size += size;
+        }
+        for key in self.evict(Some(&key)) {
+            self.remove_files(&key);
+        }
+        Ok(())
+    }
+
+    /// unindexes the least recently used files until the cache fits its size limit, returning
+    /// their keys. `keep` is never evicted
+    fn evict(&self, keep: Option<&str>) -> Vec<String> {
+        let mut index = self.index.lock().unwrap();
+        if index.total_size <= self.max_size {
+            return Vec::new();
+        }
+
+        let mut by_last_use: Vec<(String, u64, SystemTime)> = index
+            .files
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != keep)
+            .map(|(key, (size, last_used))| (key.clone(), *size, *last_used))
+            .collect();
+        by_last_use.sort_by_key(|(_, _, last_used)| *last_used);
+
+        let mut evicted = Vec::new();
+        for (key, size, _) in by_last_use {
+            if index.total_size <= self.max_size {
+                break;
+            }
+            index.files.remove(&key);
+            index.total_size -= size;
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    /// marks a file as just used, also in its modification time so the order survives restarts
+    fn touch(&self, key: &str) {
+        let now = SystemTime::now();
+        if let Some((_, last_used)) = self.index.lock().unwrap().files.get_mut(key) {
+            *last_used = now;
+        }
+        if let Err(e) = std::fs::File::options()
+            .write(true)
+            .open(self.data_path(key))
+            .and_then(|file| file.set_modified(now))
+        {
+            log::debug!("Error updating the last use of cached file {}: {}", key, e);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        {
+            let mut index = self.index.lock().unwrap();
+            if let Some((size, _)) = index.files.remove(key) {
+                index.total_size -= size;
+            }
+        }
+        self.remove_files(key);
+    }
+
+    fn remove_files(&self, key: &str) {
+        for path in [self.data_path(key), self.entry_path(key)] {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Error removing cached file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    fn data_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(DATA_EXTENSION)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(ENTRY_EXTENSION)
+    }
+}
+
+/// file name of an object in the cache, safe whatever characters the URL and path hold
+fn cache_key(store_url: &str, path: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(store_url.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORE: &str = "memory://cache-tests";
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("flat-head-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn meta(path: &str, size: usize, e_tag: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: path.into(),
+            last_modified: Default::default(),
+            size,
+            e_tag: Some(e_tag.to_string()),
+            version: None,
+        }
+    }
+
+    async fn put(cache: &BundleCache, path: &str, bytes: &'static [u8]) {
+        cache
+            .put(STORE, path, &meta(path, bytes.len(), "1"), &bytes.into())
+            .await
+            .unwrap();
+    }
+
+    async fn get(cache: &BundleCache, path: &str, size: usize) -> Option<Bytes> {
+        cache
+            .get(STORE, path, &meta(path, size, "1"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cached_copy_round_trip() {
+        let dir = cache_dir("round-trip");
+        let cache = BundleCache::open(&dir, CacheOptions::default()).unwrap();
+
+        put(&cache, "a.dbin.zst", b"abcd").await;
+        assert_eq!(get(&cache, "a.dbin.zst", 4).await.unwrap(), &b"abcd"[..]);
+        // keyed by store too
+        let other_store = cache
+            .get("memory://other", "a.dbin.zst", &meta("a.dbin.zst", 4, "1"))
+            .await
+            .unwrap();
+        assert!(other_store.is_none());
+
+        // no partial file is left behind
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().all(|name| !name.ends_with(PARTIAL_EXTENSION)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_copy_is_dropped() {
+        let dir = cache_dir("stale");
+        let cache = BundleCache::open(&dir, CacheOptions::default()).unwrap();
+        put(&cache, "a.dbin.zst", b"abcd").await;
+        put(&cache, "b.dbin.zst", b"efgh").await;
+
+        // the object changed in the store since it was cached
+        let changed_e_tag = cache
+            .get(STORE, "a.dbin.zst", &meta("a.dbin.zst", 4, "2"))
+            .await
+            .unwrap();
+        assert!(changed_e_tag.is_none());
+        assert!(get(&cache, "b.dbin.zst", 5).await.is_none());
+
+        // and the stale copies are gone
+        assert!(get(&cache, "a.dbin.zst", 4).await.is_none());
+        assert!(get(&cache, "b.dbin.zst", 4).await.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(cache.index.lock().unwrap().total_size, 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_files_are_evicted() {
+        let dir = cache_dir("evict");
+        let cache = BundleCache::open(&dir, CacheOptions { max_size: Some(10) }).unwrap();
+
+        put(&cache, "a.dbin.zst", b"abcd").await;
+        put(&cache, "b.dbin.zst", b"efgh").await;
+        // a is now used more recently than b
+        assert!(get(&cache, "a.dbin.zst", 4).await.is_some());
+        put(&cache, "c.dbin.zst", b"ijkl").await;
+
+        assert!(get(&cache, "a.dbin.zst", 4).await.is_some());
+        assert!(get(&cache, "b.dbin.zst", 4).await.is_none());
+        assert!(get(&cache, "c.dbin.zst", 4).await.is_some());
+        assert_eq!(cache.index.lock().unwrap().total_size, 8);
+
+        // a file larger than the whole cache is not cached
+        put(&cache, "d.dbin.zst", b"mnopqrstuvwxyz").await;
+        assert!(get(&cache, "d.dbin.zst", 14).await.is_none());
+        assert!(get(&cache, "a.dbin.zst", 4).await.is_some());
+
+        // a lower limit evicts on open
+        drop(cache);
+        let cache = BundleCache::open(&dir, CacheOptions { max_size: Some(4) }).unwrap();
+        assert_eq!(cache.index.lock().unwrap().files.len(), 1);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_indexes_complete_files_and_drops_partial_and_orphan_ones() {
+        let dir = cache_dir("reopen");
+        let cache = BundleCache::open(&dir, CacheOptions::default()).unwrap();
+        put(&cache, "a.dbin.zst", b"abcd").await;
+        drop(cache);
+
+        // an interrupted write, and a data file whose entry was never renamed into place
+        std::fs::write(dir.join("interrupted.data.partial"), b"ab").unwrap();
+        std::fs::write(dir.join("orphan.data"), b"ab").unwrap();
+        std::fs::write(dir.join("orphan-entry.json"), b"{}").unwrap();
+
+        let cache = BundleCache::open(&dir, CacheOptions::default()).unwrap();
+        assert!(!dir.join("interrupted.data.partial").exists());
+        assert!(!dir.join("orphan.data").exists());
+        assert!(!dir.join("orphan-entry.json").exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(cache.index.lock().unwrap().files.len(), 1);
+        assert_eq!(cache.index.lock().unwrap().total_size, 4);
+        assert_eq!(get(&cache, "a.dbin.zst", 4).await.unwrap(), &b"abcd"[..]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::era_verifier::EpochStatus;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid checkpoint file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What a checkpoint's outcomes were checked against. Outcomes of another store, or of
+/// other checks, say nothing about this run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointKey {
+    pub store_url: String,
+    pub compatible: Option<String>,
+    pub check_transactions_root: bool,
+    pub check_receipts_root: bool,
+}
+
+/// Outcome of every epoch checked so far, persisted as JSON so an interrupted run can be resumed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// checkpoints written before the key was recorded match no run
+    #[serde(default)]
+    pub key: CheckpointKey,
+    pub epochs: BTreeMap<usize, EpochStatus>,
+}
+
+impl Checkpoint {
+    pub fn new(key: CheckpointKey) -> Self {
+        Checkpoint {
+            key,
+            epochs: BTreeMap::new(),
+        }
+    }
+
+    /// loads a checkpoint file. A file that does not exist yet is an empty checkpoint
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CheckpointError> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// loads the checkpoint file of a run checked against `key`. A checkpoint written for
+    /// another key is ignored, and replaced by an empty one the next time it is saved
+    pub fn load_for(path: impl AsRef<Path>, key: &CheckpointKey) -> Result<Self, CheckpointError> {
+        let path = path.as_ref();
+        let checkpoint = Self::load(path)?;
+        if checkpoint.key == *key {
+            return Ok(checkpoint);
+        }
+
+        if !checkpoint.epochs.is_empty() {
+            log::warn!(
+                "Ignoring checkpoint file {}: it was written for store {} with other checks",
+                path.display(),
+                checkpoint.key.store_url
+            );
+        }
+        Ok(Checkpoint::new(key.clone()))
+    }
+
+    /// writes the checkpoint next to `path` first and then renames it, so a crash while saving
+    /// never leaves a truncated checkpoint behind
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+
+        std::fs::write(&partial_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&partial_path, path)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, epoch: usize, status: EpochStatus) {
+        self.epochs.insert(epoch, status);
+    }
+
+    /// whether the epoch was found valid by an earlier run
+    pub fn passed(&self, epoch: usize) -> bool {
+        self.epochs.get(&epoch) == Some(&EpochStatus::Valid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "flat-head-checkpoint-{}-{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn missing_file_is_an_empty_checkpoint() {
+        let checkpoint = Checkpoint::load(checkpoint_path("missing")).unwrap();
+        assert_eq!(checkpoint, Checkpoint::default());
+    }
+
+    #[test]
+    fn saved_checkpoint_loads_back() {
+        let path = checkpoint_path("round-trip");
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.record(3, EpochStatus::Valid);
+        checkpoint.record(1, EpochStatus::MissingBundles);
+        checkpoint.record(2, EpochStatus::ValidationError("bad epoch".to_string()));
+        checkpoint.save(&path).unwrap();
+
+        let loaded = Checkpoint::load(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.passed(3));
+        assert!(!loaded.passed(1));
+        assert!(!loaded.passed(2));
+        assert!(!loaded.passed(4));
+
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+        assert!(!PathBuf::from(partial_path).exists());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn later_outcome_replaces_earlier_one() {
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.record(5, EpochStatus::AccumulatorMismatch);
+        checkpoint.record(5, EpochStatus::Valid);
+        assert!(checkpoint.passed(5));
+        assert_eq!(checkpoint.epochs.len(), 1);
+    }
+
+    #[test]
+    fn checkpoint_of_another_run_is_ignored() {
+        let path = checkpoint_path("other-run");
+        let key = CheckpointKey {
+            store_url: "s3://blocks/mainnet".to_string(),
+            check_transactions_root: true,
+            ..Default::default()
+        };
+        let mut checkpoint = Checkpoint::new(key.clone());
+        checkpoint.record(3, EpochStatus::Valid);
+        checkpoint.save(&path).unwrap();
+
+        assert_eq!(Checkpoint::load_for(&path, &key).unwrap(), checkpoint);
+
+        let other_store = CheckpointKey {
+            store_url: "s3://blocks/sepolia".to_string(),
+            ..key.clone()
+        };
+        let loaded = Checkpoint::load_for(&path, &other_store).unwrap();
+        assert_eq!(loaded, Checkpoint::new(other_store));
+        assert!(!loaded.passed(3));
+
+        // an epoch valid without the receipts check may still fail with it
+        let more_checks = CheckpointKey {
+            check_receipts_root: true,
+            ..key
+        };
+        assert!(!Checkpoint::load_for(&path, &more_checks).unwrap().passed(3));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_without_key_matches_no_run() {
+        let path = checkpoint_path("no-key");
+        std::fs::write(&path, br#"{"epochs": {"3": "Valid"}}"#).unwrap();
+
+        assert!(Checkpoint::load(&path).unwrap().passed(3));
+        let key = CheckpointKey {
+            store_url: "file:///blocks".to_string(),
+            ..Default::default()
+        };
+        assert!(!Checkpoint::load_for(&path, &key).unwrap().passed(3));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_file_is_an_error() {
+        let path = checkpoint_path("invalid");
+        std::fs::write(&path, b"not json").unwrap();
+
+        assert!(matches!(
+            Checkpoint::load(&path),
+            Err(CheckpointError::Json(_))
+        ));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,512 @@
+use std::{fmt, ops::Range};
+
+use futures::{
+    future,
+    stream::{self, StreamExt},
+};
+use thiserror::Error;
+
+use sf_protos::ethereum::r#type::v2::{Block, BlockHeader};
+
+use crate::{
+    era_verifier::DEFAULT_MAX_CONCURRENT_READS,
+    store::{ReadError, Store},
+};
+
+/// most bundles of either store a span of compared blocks may cover. Both stores' bundles of a
+/// span are held in memory, so layouts whose bundles rarely line up are refused
+pub const MAX_SPAN_BUNDLES: u64 = 16;
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Bundles of {left} and {right} blocks only line up every {span} blocks, more than {MAX_SPAN_BUNDLES} bundles of either store")]
+    SpanTooLarge { left: u64, right: u64, span: u64 },
+}
+
+/// First field found to differ between the two copies of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockDifference {
+    /// The block is only in the left store's bundle.
+    OnlyInLeft,
+    /// The block is only in the right store's bundle.
+    OnlyInRight,
+    /// The hash or a field of the header differs, named as in the firehose header.
+    Header { field: &'static str },
+    /// The blocks hold a different number of transactions.
+    TransactionCount { left: usize, right: usize },
+    /// The transaction trace at this index differs.
+    TransactionTrace { index: usize },
+    /// Headers and traces match, but another part of the block differs.
+    Other,
+}
+
+impl fmt::Display for BlockDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockDifference::OnlyInLeft => write!(f, "only in left store"),
+            BlockDifference::OnlyInRight => write!(f, "only in right store"),
+            BlockDifference::Header { field } => write!(f, "header {} differs", field),
+            BlockDifference::TransactionCount { left, right } => {
+                write!(f, "transaction count differs: {} vs {}", left, right)
+            }
+            BlockDifference::TransactionTrace { index } => {
+                write!(f, "transaction trace {} differs", index)
+            }
+            BlockDifference::Other => write!(f, "block differs outside header and traces"),
+        }
+    }
+}
+
+/// Differences found between two stores over a block range.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    /// blocks found in both stores
+    pub compared_blocks: usize,
+    /// blocks that differ, with the first field that differs
+    pub differences: Vec<(u64, BlockDifference)>,
+    /// bundle files of the left store none of whose blocks are in a bundle of the right store
+    pub only_in_left: Vec<String>,
+    /// bundle files of the right store none of whose blocks are in a bundle of the left store
+    pub only_in_right: Vec<String>,
+    /// bundle files that could not be read from either store, with the error
+    pub read_errors: Vec<(String, String)>,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+            && self.only_in_left.is_empty()
+            && self.only_in_right.is_empty()
+            && self.read_errors.is_empty()
+    }
+}
+
+/// compares the blocks in `start_block..end_block` of two stores, bundle by bundle.
+///
+/// The stores may split blocks into bundles of different sizes, in which case the blocks are
+/// compared over spans of blocks that start and end on a bundle boundary in both stores.
+/// Fails if such a span covers more than [`MAX_SPAN_BUNDLES`] bundles of either store.
+pub async fn diff_stores(
+    left: &Store,
+    right: &Store,
+    start_block: u64,
+    end_block: u64,
+) -> Result<DiffReport, DiffError> {
+    let (left_size, right_size) = (left.layout().bundle_size(), right.layout().bundle_size());
+    let span = lcm(left_size, right_size);
+    if span / left_size.min(right_size) > MAX_SPAN_BUNDLES {
+        return Err(DiffError::SpanTooLarge {
+            left: left_size,
+            right: right_size,
+            span,
+        });
+    }
+
+    let mut spans = stream::iter((start_block / span * span..end_block).step_by(span as usize))
+        .map(|span_start| {
+            let span_end = span_start + span;
+            future::join(
+                read_span(left, span_start, span_end),
+                read_span(right, span_start, span_end),
+            )
+        })
+        .buffered(DEFAULT_MAX_CONCURRENT_READS);
+
+    let mut report = DiffReport::default();
+
+    while let Some((left_span, right_span)) = spans.next().await {
+        // blocks of a bundle that could not be read would show up as differences
+        let failed = !left_span.errors.is_empty() || !right_span.errors.is_empty();
+        for (file_name, e) in left_span.errors.into_iter().chain(right_span.errors) {
+            log::error!("Error reading {}: {}", file_name, e);
+            report.read_errors.push((file_name, e.to_string()));
+        }
+        if failed {
+            continue;
+        }
+
+        if left_span.bundles.is_empty() && right_span.bundles.is_empty() {
+            log::debug!("Blocks {}.. are in neither store", left_span.start);
+            continue;
+        }
+
+        // a bundle without a counterpart is reported once, rather than block by block
+        let left_ranges = bundle_ranges(&left_span.bundles);
+        let right_ranges = bundle_ranges(&right_span.bundles);
+        let (left_bundles, only_in_left) = split_unmatched(left_span.bundles, &right_ranges);
+        let (right_bundles, only_in_right) = split_unmatched(right_span.bundles, &left_ranges);
+        report.only_in_left.extend(only_in_left);
+        report.only_in_right.extend(only_in_right);
+
+        let in_range = |block: &&Block| (start_block..end_block).contains(&block.number);
+        diff_bundle(
+            left_bundles
+                .iter()
+                .flat_map(|bundle| bundle.blocks.iter())
+                .filter(in_range)
+                .collect(),
+            right_bundles
+                .iter()
+                .flat_map(|bundle| bundle.blocks.iter())
+                .filter(in_range)
+                .collect(),
+            &mut report,
+        );
+    }
+
+    Ok(report)
+}
+
+/// Blocks of one store over a span of blocks, read from one or more bundle files.
+struct Span {
+    start: u64,
+    /// bundle files found, in order
+    bundles: Vec<FoundBundle>,
+    /// bundle files that could not be read for another reason than not being there
+    errors: Vec<(String, ReadError)>,
+}
+
+struct FoundBundle {
+    file_name: String,
+    /// blocks the bundle file covers in its store's layout
+    range: Range<u64>,
+    blocks: Vec<Block>,
+}
+
+fn bundle_ranges(bundles: &[FoundBundle]) -> Vec<Range<u64>> {
+    bundles.iter().map(|bundle| bundle.range.clone()).collect()
+}
+
+/// splits `bundles` into the ones sharing blocks with one of the `others` ranges, and the file
+/// names of the ones that do not
+fn split_unmatched(
+    bundles: Vec<FoundBundle>,
+    others: &[Range<u64>],
+) -> (Vec<FoundBundle>, Vec<String>) {
+    let (matched, unmatched): (Vec<_>, Vec<_>) = bundles.into_iter().partition(|bundle| {
+        others
+            .iter()
+            .any(|other| other.start < bundle.range.end && bundle.range.start < other.end)
+    });
+    (
+        matched,
+        unmatched
+            .into_iter()
+            .map(|bundle| bundle.file_name)
+            .collect(),
+    )
+}
+
+async fn read_span(store: &Store, start: u64, end: u64) -> Span {
+    let layout = store.layout();
+    let bundles = future::join_all(
+        layout
+            .bundle_starts(start, end)
+            .map(
+                |bundle_start| async move { (bundle_start, store.read_bundle(bundle_start).await) },
+            ),
+    )
+    .await;
+
+    let mut span = Span {
+        start,
+        bundles: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (bundle_start, (file_name, bundle)) in bundles {
+        match bundle {
+            Ok(blocks) => span.bundles.push(FoundBundle {
+                file_name,
+                range: bundle_start..bundle_start + layout.bundle_size(),
+                blocks,
+            }),
+            Err(e) if e.is_not_found() => {}
+            Err(e) => span.errors.push((file_name, e)),
+        }
+    }
+    span
+}
+
+fn diff_bundle(left: Vec<&Block>, right: Vec<&Block>, report: &mut DiffReport) {
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    // both bundles are ordered by block number
+    loop {
+        match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) if l.number == r.number => {
+                report.compared_blocks += 1;
+                if let Some(difference) = first_difference(l, r) {
+                    report.differences.push((l.number, difference));
+                }
+                left.next();
+                right.next();
+            }
+            (Some(l), Some(r)) if l.number < r.number => {
+                report
+                    .differences
+                    .push((l.number, BlockDifference::OnlyInLeft));
+                left.next();
+            }
+            (Some(l), None) => {
+                report
+                    .differences
+                    .push((l.number, BlockDifference::OnlyInLeft));
+                left.next();
+            }
+            (_, Some(r)) => {
+                report
+                    .differences
+                    .push((r.number, BlockDifference::OnlyInRight));
+                right.next();
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+/// finds the first field that differs between two copies of the same block
+pub fn first_difference(left: &Block, right: &Block) -> Option<BlockDifference> {
+    if left == right {
+        return None;
+    }
+
+    if left.hash != right.hash {
+        return Some(BlockDifference::Header { field: "hash" });
+    }
+    if left.header != right.header {
+        let field = match (&left.header, &right.header) {
+            (Some(left), Some(right)) => header_difference(left, right),
+            _ => None,
+        };
+        // a header missing from one copy, or a field not listed in `header_difference`
+        return Some(BlockDifference::Header {
+            field: field.unwrap_or("header"),
+        });
+    }
+
+    if left.transaction_traces.len() != right.transaction_traces.len() {
+        return Some(BlockDifference::TransactionCount {
+            left: left.transaction_traces.len(),
+            right: right.transaction_traces.len(),
+        });
+    }
+
+    if let Some(index) = left
+        .transaction_traces
+        .iter()
+        .zip(right.transaction_traces.iter())
+        .position(|(l, r)| l != r)
+    {
+        return Some(BlockDifference::TransactionTrace { index });
+    }
+
+    Some(BlockDifference::Other)
+}
+
+/// name of the first field that differs between two headers
+fn header_difference(left: &BlockHeader, right: &BlockHeader) -> Option<&'static str> {
+    [
+        ("parent_hash", left.parent_hash != right.parent_hash),
+        ("uncle_hash", left.uncle_hash != right.uncle_hash),
+        ("coinbase", left.coinbase != right.coinbase),
+        ("state_root", left.state_root != right.state_root),
+        (
+            "transactions_root",
+            left.transactions_root != right.transactions_root,
+        ),
+        ("receipt_root", left.receipt_root != right.receipt_root),
+        ("logs_bloom", left.logs_bloom != right.logs_bloom),
+        ("difficulty", left.difficulty != right.difficulty),
+        (
+            "total_difficulty",
+            left.total_difficulty != right.total_difficulty,
+        ),
+        ("number", left.number != right.number),
+        ("gas_limit", left.gas_limit != right.gas_limit),
+        ("gas_used", left.gas_used != right.gas_used),
+        ("timestamp", left.timestamp != right.timestamp),
+        ("extra_data", left.extra_data != right.extra_data),
+        ("mix_hash", left.mix_hash != right.mix_hash),
+        ("nonce", left.nonce != right.nonce),
+        ("hash", left.hash != right.hash),
+        (
+            "base_fee_per_gas",
+            left.base_fee_per_gas != right.base_fee_per_gas,
+        ),
+        (
+            "withdrawals_root",
+            left.withdrawals_root != right.withdrawals_root,
+        ),
+        ("blob_gas_used", left.blob_gas_used != right.blob_gas_used),
+        (
+            "excess_blob_gas",
+            left.excess_blob_gas != right.excess_blob_gas,
+        ),
+        (
+            "parent_beacon_root",
+            left.parent_beacon_root != right.parent_beacon_root,
+        ),
+    ]
+    .into_iter()
+    .find(|(_, differs)| *differs)
+    .map(|(field, _)| field)
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::{path::Path, ObjectStore};
+    use sf_protos::ethereum::r#type::v2::TransactionTrace;
+
+    use super::*;
+    use crate::{
+        layout::BundleLayout,
+        memory::{load_dir, named_memory_store, store_from_dir},
+        store,
+    };
+
+    const FIXTURE_DIR: &str = "tests/ethereum_firehose_first_8200";
+
+    fn block(number: u64) -> Block {
+        Block {
+            number,
+            hash: vec![number as u8; 32],
+            header: Some(BlockHeader {
+                number,
+                gas_used: 21_000,
+                ..Default::default()
+            }),
+            transaction_traces: vec![TransactionTrace::default(); 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identical_blocks_have_no_difference() {
+        assert_eq!(first_difference(&block(1), &block(1)), None);
+    }
+
+    #[test]
+    fn differing_header_field_is_named() {
+        let mut right = block(1);
+        right.header.as_mut().unwrap().gas_used += 1;
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::Header { field: "gas_used" })
+        );
+
+        right.hash = vec![0xff; 32];
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::Header { field: "hash" })
+        );
+
+        right = block(1);
+        right.header = None;
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::Header { field: "header" })
+        );
+    }
+
+    #[test]
+    fn differing_traces_are_located() {
+        let mut right = block(1);
+        right.transaction_traces.pop();
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::TransactionCount { left: 2, right: 1 })
+        );
+
+        right = block(1);
+        right.transaction_traces[1].nonce = 7;
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::TransactionTrace { index: 1 })
+        );
+
+        right = block(1);
+        right.size = 100;
+        assert_eq!(
+            first_difference(&block(1), &right),
+            Some(BlockDifference::Other)
+        );
+    }
+
+    #[test]
+    fn blocks_missing_from_one_bundle_are_reported() {
+        let mut report = DiffReport::default();
+        let (one, two, three) = (block(1), block(2), block(3));
+        diff_bundle(vec![&one, &two], vec![&two, &three], &mut report);
+
+        assert_eq!(report.compared_blocks, 1);
+        assert_eq!(
+            report.differences,
+            vec![
+                (1, BlockDifference::OnlyInLeft),
+                (3, BlockDifference::OnlyInRight)
+            ]
+        );
+    }
+
+    #[test]
+    fn bundles_without_counterpart_are_split_off() {
+        let bundle = |start: u64, size: u64| FoundBundle {
+            file_name: format!("{:010}.dbin", start),
+            range: start..start + size,
+            blocks: Vec::new(),
+        };
+        let (matched, unmatched) = split_unmatched(
+            vec![bundle(0, 100), bundle(100, 100), bundle(200, 100)],
+            &[0..150, 1000..1100],
+        );
+
+        let matched: Vec<_> = matched.iter().map(|bundle| bundle.range.start).collect();
+        assert_eq!(matched, vec![0, 100]);
+        assert_eq!(unmatched, vec!["0000000200.dbin".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn layouts_that_rarely_line_up_are_refused() {
+        let layout = |bundle_size| BundleLayout {
+            bundle_size: Some(bundle_size),
+            ..Default::default()
+        };
+        let left = store::new("memory://diff-span-left", None)
+            .unwrap()
+            .with_layout(layout(100));
+        let right = store::new("memory://diff-span-right", None)
+            .unwrap()
+            .with_layout(layout(8192));
+
+        assert!(matches!(
+            diff_stores(&left, &right, 0, 8192).await,
+            Err(DiffError::SpanTooLarge { span: 204_800, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_bundle_is_reported_once() {
+        let left = store_from_dir(FIXTURE_DIR).await.unwrap();
+        let memory = named_memory_store("diff-missing-bundle");
+        load_dir(memory.as_ref(), FIXTURE_DIR, &Path::default())
+            .await
+            .unwrap();
+        memory.delete(&Path::from("0000000300.dbin")).await.unwrap();
+        let right = store::new("memory://diff-missing-bundle", None).unwrap();
+
+        let report = diff_stores(&left, &right, 0, 1000).await.unwrap();
+        assert_eq!(report.only_in_left, vec!["0000000300.dbin".to_string()]);
+        assert!(report.only_in_right.is_empty());
+        assert!(report.differences.is_empty());
+        assert_eq!(report.compared_blocks, 900);
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::{hex, B256};
+use ssz::Encode;
+use tree_hash::TreeHash;
+
+use ethportal_api::types::execution::accumulator::{EpochAccumulator, HeaderRecord};
+use header_accumulator::types::ExtHeaderRecord;
+
+/// builds the epoch accumulator of an epoch's header records
+pub fn epoch_accumulator(headers: &[ExtHeaderRecord]) -> EpochAccumulator {
+    let header_records: Vec<HeaderRecord> = headers
+        .iter()
+        .map(|header| HeaderRecord {
+            block_hash: header.block_hash,
+            total_difficulty: header.total_difficulty,
+        })
+        .collect();
+    EpochAccumulator::from(header_records)
+}
+
+/// root of the epoch accumulator built from an epoch's header records
+pub fn epoch_accumulator_root(headers: &[ExtHeaderRecord]) -> B256 {
+    epoch_accumulator(headers).tree_hash_root()
+}
+
+/// name of the file an epoch accumulator is exported to, e.g. `epoch-accumulator-00000-0x5ec1...`
+pub fn epoch_accumulator_file_name(epoch: usize, root: &B256) -> String {
+    format!(
+        "epoch-accumulator-{:05}-{}.ssz",
+        epoch,
+        hex::encode_prefixed(root)
+    )
+}
+
+/// writes the SSZ epoch accumulator of `headers` to `dir`, the way Portal clients store it,
+/// returning the path of the new file
+pub async fn export_epoch_accumulator(
+    dir: &Path,
+    epoch: usize,
+    headers: &[ExtHeaderRecord],
+) -> Result<PathBuf, std::io::Error> {
+    let epoch_accumulator = epoch_accumulator(headers);
+    let path = dir.join(epoch_accumulator_file_name(
+        epoch,
+        &epoch_accumulator.tree_hash_root(),
+    ));
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&path, epoch_accumulator.as_ssz_bytes()).await?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use ssz::Decode;
+
+    use super::*;
+
+    fn headers(count: u64) -> Vec<ExtHeaderRecord> {
+        (0..count)
+            .map(|block_number| ExtHeaderRecord {
+                block_hash: B256::with_last_byte(block_number as u8),
+                total_difficulty: U256::from(block_number + 1),
+                block_number,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_are_encoded_as_hash_and_little_endian_difficulty() {
+        let bytes = epoch_accumulator(&headers(2)).as_ssz_bytes();
+        assert_eq!(bytes.len(), 128);
+        assert_eq!(bytes[31], 0);
+        assert_eq!(bytes[32], 1);
+        assert_eq!(bytes[95], 1);
+        assert_eq!(bytes[96], 2);
+    }
+
+    #[tokio::test]
+    async fn exported_accumulator_is_named_after_its_root() {
+        let dir =
+            std::env::temp_dir().join(format!("flat-head-accumulators-{}", std::process::id()));
+        let headers = headers(5);
+
+        let path = export_epoch_accumulator(&dir, 3, &headers).await.unwrap();
+        let root = epoch_accumulator_root(&headers);
+        assert_eq!(path, dir.join(epoch_accumulator_file_name(3, &root)));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("epoch-accumulator-00003-0x"));
+
+        let exported = EpochAccumulator::from_ssz_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(exported.tree_hash_root(), root);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
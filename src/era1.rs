@@ -0,0 +1,855 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use alloy_primitives::{hex, keccak256, B256, U256};
+use alloy_rlp::{Encodable, Header};
+use futures::stream::{self, Stream, StreamExt};
+use snap::{read::FrameDecoder, write::FrameEncoder};
+use thiserror::Error;
+use tree_hash::TreeHash;
+
+use ethportal_api::types::execution::accumulator::{EpochAccumulator, HeaderRecord};
+use header_accumulator::types::ExtHeaderRecord;
+use sf_protos::ethereum::r#type::v2::Block;
+use trin_validation::accumulator::PreMergeAccumulator;
+
+use crate::{
+    epoch_accumulator::epoch_accumulator_root,
+    era_verifier::{
+        validate_epoch_from_store, EpochStatus, VerifyOptions, DEFAULT_MAX_CONCURRENT_READS,
+        FINAL_EPOCH, MAX_EPOCH_SIZE, MERGE_BLOCK,
+    },
+    headers::{check_header_hash, encode_header, header_number, HeaderError},
+    receipts::{check_receipts_root, encode_receipt, ReceiptError},
+    store::{ReadError, Store},
+    transactions::{check_transactions_root, encode_transaction, TransactionError},
+};
+
+/// e2store entry types used by era1 files
+pub const VERSION: [u8; 2] = [0x65, 0x32];
+pub const COMPRESSED_HEADER: [u8; 2] = [0x03, 0x00];
+pub const COMPRESSED_BODY: [u8; 2] = [0x04, 0x00];
+pub const COMPRESSED_RECEIPTS: [u8; 2] = [0x05, 0x00];
+pub const TOTAL_DIFFICULTY: [u8; 2] = [0x06, 0x00];
+pub const ACCUMULATOR: [u8; 2] = [0x07, 0x00];
+pub const BLOCK_INDEX: [u8; 2] = [0x66, 0x32];
+
+/// size of the header in front of every e2store entry: type, length and reserved bytes
+const ENTRY_HEADER_SIZE: u64 = 8;
+
+#[derive(Error, Debug)]
+pub enum Era1Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Epoch {0} is after the merge and cannot be exported to era1")]
+    PostMergeEpoch(usize),
+    #[error("Epoch {epoch} did not validate: {status}")]
+    InvalidEpoch { epoch: usize, status: EpochStatus },
+    #[error("Read error: {0}")]
+    Read(#[from] ReadError),
+    #[error("Header error: {0}")]
+    Header(#[from] HeaderError),
+    #[error("Transaction error: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error("Receipt error: {0}")]
+    Receipt(#[from] ReceiptError),
+    #[error("Block {0} is missing from the flat files")]
+    MissingBlock(u64),
+    #[error("Block {0} does not match the header record it was validated with")]
+    BlockMismatch(u64),
+    #[error("Invalid era1 file: {0}")]
+    InvalidEra1(String),
+    #[error("Era1 accumulator of epoch {0} does not match the master accumulator")]
+    AccumulatorMismatch(usize),
+}
+
+/// Writes the entries of an era1 file, block by block.
+///
+/// The version entry is written on creation, and the accumulator and block index entries
+/// by [`Era1Writer::finish`].
+pub struct Era1Writer<W: Write> {
+    writer: W,
+    /// bytes written so far
+    position: u64,
+    start_block: Option<u64>,
+    /// position of the header entry of every block written so far
+    header_offsets: Vec<u64>,
+}
+
+impl<W: Write> Era1Writer<W> {
+    pub fn new(writer: W) -> Result<Self, io::Error> {
+        let mut era1_writer = Era1Writer {
+            writer,
+            position: 0,
+            start_block: None,
+            header_offsets: Vec::with_capacity(MAX_EPOCH_SIZE),
+        };
+        era1_writer.write_entry(VERSION, &[])?;
+        Ok(era1_writer)
+    }
+
+    /// writes the header, body, receipts and total difficulty entries of a block
+    pub fn write_block(
+        &mut self,
+        block: &Block,
+        header_record: &ExtHeaderRecord,
+    ) -> Result<(), Era1Error> {
+        if block.number != header_record.block_number
+            || block.hash.as_slice() != header_record.block_hash.0.as_slice()
+        {
+            return Err(Era1Error::BlockMismatch(block.number));
+        }
+        let header = block
+            .header
+            .as_ref()
+            .ok_or(HeaderError::MissingHeader(block.number))?;
+
+        self.start_block.get_or_insert(block.number);
+        self.header_offsets.push(self.position);
+
+        self.write_entry(COMPRESSED_HEADER, &compress(&encode_header(header))?)?;
+        self.write_entry(COMPRESSED_BODY, &compress(&encode_body(block)?)?)?;
+        self.write_entry(COMPRESSED_RECEIPTS, &compress(&encode_receipts(block)?)?)?;
+        self.write_entry(
+            TOTAL_DIFFICULTY,
+            &header_record.total_difficulty.to_le_bytes::<32>(),
+        )?;
+
+        Ok(())
+    }
+
+    /// writes the accumulator and block index entries, returning the underlying writer
+    pub fn finish(mut self, accumulator_root: B256) -> Result<W, io::Error> {
+        self.write_entry(ACCUMULATOR, accumulator_root.as_slice())?;
+
+        // offsets are relative to the start of the block index entry
+        let index_position = self.position as i64;
+        let mut index = Vec::with_capacity(16 + 8 * self.header_offsets.len());
+        index.extend_from_slice(&self.start_block.unwrap_or_default().to_le_bytes());
+        for offset in self.header_offsets.iter() {
+            index.extend_from_slice(&(*offset as i64 - index_position).to_le_bytes());
+        }
+        index.extend_from_slice(&(self.header_offsets.len() as u64).to_le_bytes());
+        self.write_entry(BLOCK_INDEX, &index)?;
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_entry(&mut self, entry_type: [u8; 2], data: &[u8]) -> Result<(), io::Error> {
+        self.writer.write_all(&entry_type)?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&[0u8; 2])?;
+        self.writer.write_all(data)?;
+        self.position += ENTRY_HEADER_SIZE + data.len() as u64;
+        Ok(())
+    }
+}
+
+/// name of the era1 file of an epoch: network, epoch and the first 4 bytes of its accumulator root
+pub fn era1_file_name(epoch: usize, accumulator_root: &B256) -> String {
+    format!(
+        "mainnet-{:05}-{}.era1",
+        epoch,
+        hex::encode(&accumulator_root[..4])
+    )
+}
+
+/// converts a pre-merge epoch of flat files from `store` into an era1 file in `out_dir`.
+///
+/// The epoch is validated against the master accumulator first, along with the transactions
+/// and receipts roots of its blocks, then its bundles are read again and written out block by
+/// block, so only a few bundles are held in memory at a time. Blocks read the second time are
+/// checked against the validated header records again before they are written.
+/// Returns the path of the era1 file.
+pub async fn export_era1(
+    store: &Store,
+    macc: &PreMergeAccumulator,
+    epoch: usize,
+    out_dir: &Path,
+) -> Result<PathBuf, Era1Error> {
+    if epoch > FINAL_EPOCH {
+        return Err(Era1Error::PostMergeEpoch(epoch));
+    }
+
+    let options = VerifyOptions {
+        check_transactions_root: true,
+        check_receipts_root: true,
+        ..Default::default()
+    };
+    let (report, headers) = validate_epoch_from_store(epoch, store, macc, &options).await;
+    let headers = headers.ok_or(Era1Error::InvalidEpoch {
+        epoch,
+        status: report.status,
+    })?;
+    let accumulator_root = epoch_accumulator_root(&headers);
+
+    std::fs::create_dir_all(out_dir)?;
+    let path = out_dir.join(era1_file_name(epoch, &accumulator_root));
+    // the era1 file only shows up under its final name once it is complete
+    let partial_path = path.with_extension("era1.partial");
+    let mut writer = Era1Writer::new(BufWriter::new(File::create(&partial_path)?))?;
+
+    let start_block = epoch * MAX_EPOCH_SIZE;
+    let end_block = ((epoch + 1) * MAX_EPOCH_SIZE).min(MERGE_BLOCK);
+    let mut bundles = read_bundles(store, start_block, end_block);
+
+    let mut header_records = headers.iter();
+    while let Some((_, bundle)) = bundles.next().await {
+        for block in bundle?
+            .iter()
+            .filter(|block| (start_block..end_block).contains(&(block.number as usize)))
+        {
+            let header_record = header_records
+                .next()
+                .ok_or(Era1Error::BlockMismatch(block.number))?;
+            // the writer checks the block hash against the record, and these checks tie the
+            // header, transactions and receipts to that hash
+            check_header_hash(block)?;
+            check_transactions_root(block)?;
+            check_receipts_root(block)?;
+            writer.write_block(block, header_record)?;
+        }
+    }
+    if let Some(header_record) = header_records.next() {
+        return Err(Era1Error::MissingBlock(header_record.block_number));
+    }
+
+    writer.finish(accumulator_root)?;
+    std::fs::rename(&partial_path, &path)?;
+
+    Ok(path)
+}
+
+/// A block of an era1 file. Its header, body and receipts stay snappy-compressed until asked for.
+#[derive(Debug, Clone)]
+pub struct Era1Block {
+    compressed_header: Vec<u8>,
+    compressed_body: Vec<u8>,
+    compressed_receipts: Vec<u8>,
+    /// total difficulty up to and including this block
+    pub total_difficulty: U256,
+}
+
+impl Era1Block {
+    /// the RLP-encoded header
+    pub fn header(&self) -> Result<Vec<u8>, io::Error> {
+        decompress(&self.compressed_header)
+    }
+
+    /// the RLP-encoded body: transactions and uncle headers
+    pub fn body(&self) -> Result<Vec<u8>, io::Error> {
+        decompress(&self.compressed_body)
+    }
+
+    /// the RLP-encoded list of receipts
+    pub fn receipts(&self) -> Result<Vec<u8>, io::Error> {
+        decompress(&self.compressed_receipts)
+    }
+}
+
+/// Blocks, accumulator root and block index of an era1 file.
+#[derive(Debug, Clone)]
+pub struct Era1 {
+    pub start_block: u64,
+    pub blocks: Vec<Era1Block>,
+    pub accumulator_root: B256,
+}
+
+impl Era1 {
+    /// parses the e2store entries of an era1 file. Entries of unknown types are skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Era1Error> {
+        let mut entries = E2StoreEntries { bytes };
+
+        match entries.next().transpose()? {
+            Some((VERSION, _)) => {}
+            _ => return Err(invalid_era1("file does not start with a version entry")),
+        }
+
+        let mut headers = Vec::new();
+        let mut bodies = Vec::new();
+        let mut receipts = Vec::new();
+        let mut total_difficulties = Vec::new();
+        let mut accumulator_root = None;
+        let mut block_index = None;
+
+        for entry in entries {
+            let (entry_type, data) = entry?;
+            match entry_type {
+                COMPRESSED_HEADER => headers.push(data.to_vec()),
+                COMPRESSED_BODY => bodies.push(data.to_vec()),
+                COMPRESSED_RECEIPTS => receipts.push(data.to_vec()),
+                TOTAL_DIFFICULTY => {
+                    if data.len() != 32 {
+                        return Err(invalid_era1("total difficulty is not 32 bytes"));
+                    }
+                    // stored little-endian
+                    total_difficulties.push(U256::from_le_slice(data))
+                }
+                ACCUMULATOR => {
+                    accumulator_root = Some(
+                        B256::try_from(data)
+                            .map_err(|_| invalid_era1("accumulator root is not 32 bytes"))?,
+                    )
+                }
+                BLOCK_INDEX => block_index = Some(data),
+                _ => {}
+            }
+        }
+
+        let accumulator_root = accumulator_root.ok_or(invalid_era1("no accumulator entry"))?;
+        let block_index = block_index.ok_or(invalid_era1("no block index entry"))?;
+        if block_index.len() < 16 {
+            return Err(invalid_era1("block index is too short"));
+        }
+        let start_block = read_u64(&block_index[..8]);
+        let count = read_u64(&block_index[block_index.len() - 8..]) as usize;
+
+        if [
+            bodies.len(),
+            receipts.len(),
+            total_difficulties.len(),
+            count,
+        ]
+        .iter()
+        .any(|len| *len != headers.len())
+        {
+            return Err(invalid_era1(
+                "headers, bodies, receipts, total difficulties and block index do not line up",
+            ));
+        }
+
+        let blocks = headers
+            .into_iter()
+            .zip(bodies)
+            .zip(receipts)
+            .zip(total_difficulties)
+            .map(
+                |(
+                    ((compressed_header, compressed_body), compressed_receipts),
+                    total_difficulty,
+                )| {
+                    Era1Block {
+                        compressed_header,
+                        compressed_body,
+                        compressed_receipts,
+                        total_difficulty,
+                    }
+                },
+            )
+            .collect();
+
+        Ok(Era1 {
+            start_block,
+            blocks,
+            accumulator_root,
+        })
+    }
+
+    pub fn try_from_file(path: impl AsRef<Path>) -> Result<Self, Era1Error> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.start_block as usize / MAX_EPOCH_SIZE
+    }
+}
+
+/// Part of a block that differs between an era1 file and the flat files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Era1Field {
+    Header,
+    Body,
+    Receipts,
+    TotalDifficulty,
+}
+
+impl fmt::Display for Era1Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Era1Field::Header => write!(f, "header"),
+            Era1Field::Body => write!(f, "body"),
+            Era1Field::Receipts => write!(f, "receipts"),
+            Era1Field::TotalDifficulty => write!(f, "total difficulty"),
+        }
+    }
+}
+
+/// Outcome of comparing an era1 file with the flat files of the same epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Era1Comparison {
+    /// blocks found in both the era1 file and the flat files
+    pub compared: usize,
+    /// blocks whose contents differ, with the part that differs
+    pub mismatches: Vec<(u64, Era1Field)>,
+    /// blocks of the era1 file missing from the flat files
+    pub missing_blocks: Vec<u64>,
+    /// bundle files that could not be read, with the error. Their blocks count as missing
+    pub read_errors: Vec<(String, String)>,
+}
+
+impl Era1Comparison {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty() && self.missing_blocks.is_empty() && self.read_errors.is_empty()
+    }
+}
+
+/// checks the headers of an era1 file against the master accumulator, returning its epoch.
+///
+/// The epoch accumulator is rebuilt from the header hashes and total difficulties, and must
+/// match both the accumulator entry of the file and the master accumulator.
+pub fn validate_era1(era1: &Era1, macc: &PreMergeAccumulator) -> Result<usize, Era1Error> {
+    let epoch = era1.epoch();
+    if era1.start_block as usize % MAX_EPOCH_SIZE != 0 {
+        return Err(invalid_era1("start block is not the start of an epoch"));
+    }
+    if epoch > FINAL_EPOCH {
+        return Err(Era1Error::PostMergeEpoch(epoch));
+    }
+    let end_block = ((epoch + 1) * MAX_EPOCH_SIZE).min(MERGE_BLOCK);
+    if era1.blocks.len() != end_block - era1.start_block as usize {
+        return Err(invalid_era1(
+            "epoch does not hold the expected number of blocks",
+        ));
+    }
+
+    let mut header_records = Vec::with_capacity(era1.blocks.len());
+    for (expected_number, block) in (era1.start_block..).zip(era1.blocks.iter()) {
+        let header = block.header()?;
+        let block_number = header_number(&header).map_err(|e| invalid_era1(&e.to_string()))?;
+        if block_number != expected_number {
+            return Err(invalid_era1(&format!(
+                "block {} found where block {} was expected",
+                block_number, expected_number
+            )));
+        }
+        header_records.push(HeaderRecord {
+            block_hash: keccak256(&header),
+            total_difficulty: block.total_difficulty,
+        });
+    }
+
+    let accumulator_root = EpochAccumulator::from(header_records).tree_hash_root();
+    let trusted_root = macc.historical_epochs.get(epoch);
+    if accumulator_root != era1.accumulator_root || Some(&accumulator_root) != trusted_root {
+        log::error!("Era1 validation failed: {}", epoch);
+        return Err(Era1Error::AccumulatorMismatch(epoch));
+    }
+
+    log::info!("Era1 validated successfully: {}", epoch);
+    Ok(epoch)
+}
+
+/// compares every block of an era1 file with the same block read from the flat files in `store`,
+/// re-encoding the firehose blocks the way era1 stores them
+pub async fn compare_era1_with_store(
+    era1: &Era1,
+    store: &Store,
+) -> Result<Era1Comparison, Era1Error> {
+    let start_block = era1.start_block as usize;
+    let end_block = start_block + era1.blocks.len();
+
+    let mut comparison = Era1Comparison::default();
+    let mut seen = vec![false; era1.blocks.len()];
+
+    let mut bundles = read_bundles(store, start_block, end_block);
+    while let Some((block_file_name, bundle)) = bundles.next().await {
+        let blocks = match bundle {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                log::error!("Error reading {}: {}", block_file_name, e);
+                comparison
+                    .read_errors
+                    .push((block_file_name, e.to_string()));
+                continue;
+            }
+        };
+
+        for block in blocks
+            .iter()
+            .filter(|block| (start_block..end_block).contains(&(block.number as usize)))
+        {
+            let index = block.number as usize - start_block;
+            seen[index] = true;
+            comparison.compared += 1;
+
+            for field in compare_block(block, &era1.blocks[index])? {
+                log::error!(
+                    "Block {}: {} differs from the era1 file",
+                    block.number,
+                    field
+                );
+                comparison.mismatches.push((block.number, field));
+            }
+        }
+    }
+
+    comparison.missing_blocks = seen
+        .iter()
+        .zip(era1.start_block..)
+        .filter(|(seen, _)| !**seen)
+        .map(|(_, block_number)| block_number)
+        .collect();
+
+    Ok(comparison)
+}
+
+/// parts of a firehose block that differ from the era1 block at the same height
+fn compare_block(block: &Block, era1_block: &Era1Block) -> Result<Vec<Era1Field>, Era1Error> {
+    let header = block
+        .header
+        .as_ref()
+        .ok_or(HeaderError::MissingHeader(block.number))?;
+
+    let mut mismatches = Vec::new();
+
+    if encode_header(header) != era1_block.header()? {
+        mismatches.push(Era1Field::Header);
+    }
+    // blocks the encoders do not support cannot match what era1 holds
+    if encode_body(block).ok() != Some(era1_block.body()?) {
+        mismatches.push(Era1Field::Body);
+    }
+    if encode_receipts(block).ok() != Some(era1_block.receipts()?) {
+        mismatches.push(Era1Field::Receipts);
+    }
+
+    let firehose_total_difficulty = header
+        .total_difficulty
+        .as_ref()
+        .map(|value| value.bytes.as_slice())
+        .unwrap_or_default();
+    if U256::try_from_be_slice(firehose_total_difficulty) != Some(era1_block.total_difficulty) {
+        mismatches.push(Era1Field::TotalDifficulty);
+    }
+
+    Ok(mismatches)
+}
+
+/// reads the bundles covering `start_block..end_block`, in order, keeping each bundle's file name
+fn read_bundles(
+    store: &Store,
+    start_block: usize,
+    end_block: usize,
+) -> impl Stream<Item = (String, Result<Vec<Block>, ReadError>)> + '_ {
+    let bundle_starts = store
+        .layout()
+        .bundle_starts(start_block as u64, end_block as u64);
+
+    stream::iter(bundle_starts)
+        .map(move |bundle_start| store.read_bundle(bundle_start))
+        .buffered(DEFAULT_MAX_CONCURRENT_READS)
+}
+
+/// iterates over the type and data of e2store entries
+struct E2StoreEntries<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for E2StoreEntries<'a> {
+    type Item = Result<([u8; 2], &'a [u8]), Era1Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        if self.bytes.len() < ENTRY_HEADER_SIZE as usize {
+            self.bytes = &[];
+            return Some(Err(invalid_era1("truncated entry header")));
+        }
+
+        let entry_type = [self.bytes[0], self.bytes[1]];
+        let length = u32::from_le_bytes(self.bytes[2..6].try_into().expect("slice is 4 bytes"));
+        let end = ENTRY_HEADER_SIZE as usize + length as usize;
+        if self.bytes.len() < end {
+            self.bytes = &[];
+            return Some(Err(invalid_era1("truncated entry data")));
+        }
+
+        let data = &self.bytes[ENTRY_HEADER_SIZE as usize..end];
+        self.bytes = &self.bytes[end..];
+        Some(Ok((entry_type, data)))
+    }
+}
+
+fn invalid_era1(message: &str) -> Era1Error {
+    Era1Error::InvalidEra1(message.to_string())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().expect("slice is 8 bytes"))
+}
+
+/// RLP-encodes a block body: its transactions and its uncle headers
+fn encode_body(block: &Block) -> Result<Vec<u8>, Era1Error> {
+    let transactions = block
+        .transaction_traces
+        .iter()
+        .map(encode_transaction)
+        .collect::<Result<Vec<_>, _>>()?;
+    let uncles: Vec<Vec<u8>> = block.uncles.iter().map(encode_header).collect();
+
+    let mut payload = Vec::new();
+    encode_envelopes(&transactions, &mut payload);
+    encode_rlp_list(&uncles, &mut payload);
+
+    let mut out = Vec::with_capacity(payload.len() + 5);
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&payload);
+    Ok(out)
+}
+
+/// RLP-encodes the receipts of a block as a list
+fn encode_receipts(block: &Block) -> Result<Vec<u8>, Era1Error> {
+    let receipts = block
+        .transaction_traces
+        .iter()
+        .map(|trace| encode_receipt(trace, block.number))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut out = Vec::new();
+    encode_envelopes(&receipts, &mut out);
+    Ok(out)
+}
+
+/// encodes a list of transactions or receipts the way they are sent over the network: legacy
+/// items as they are, typed items wrapped in an RLP string
+fn encode_envelopes(items: &[Vec<u8>], out: &mut Vec<u8>) {
+    let mut payload = Vec::new();
+    for item in items {
+        // RLP lists start at 0xc0, while transaction types stay below 0x7f
+        if item.first().is_some_and(|byte| *byte >= 0xc0) {
+            payload.extend_from_slice(item);
+        } else {
+            item.as_slice().encode(&mut payload);
+        }
+    }
+
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(out);
+    out.extend_from_slice(&payload);
+}
+
+/// wraps already RLP-encoded items in a list
+fn encode_rlp_list(items: &[Vec<u8>], out: &mut Vec<u8>) {
+    let payload_length = items.iter().map(Vec::len).sum();
+    Header {
+        list: true,
+        payload_length,
+    }
+    .encode(out);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut out = Vec::new();
+    FrameDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// compresses an entry with the snappy framing format
+fn compress(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut encoder = FrameEncoder::new(Vec::with_capacity(data.len()));
+    encoder.write_all(data)?;
+    encoder
+        .into_inner()
+        .map_err(|e| io::Error::new(e.error().kind(), e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use sf_protos::ethereum::r#type::v2::{BigInt, BlockHeader};
+
+    use alloy_primitives::b256;
+
+    use super::*;
+    use crate::{headers::header_hash, memory::store_from_dir, store::decode_bundle};
+
+    /// epoch accumulator root of the first mainnet epoch
+    const MAINNET_EPOCH_0_ROOT: B256 =
+        b256!("5ec1ffb8c3b146f42606c74ced973dc16ec5a107c0345858c343fc94780b4218");
+
+    fn block(number: u64) -> Block {
+        let header = BlockHeader {
+            number,
+            logs_bloom: vec![0; 256],
+            difficulty: Some(BigInt { bytes: vec![1] }),
+            total_difficulty: Some(BigInt {
+                bytes: (number + 1).to_be_bytes().to_vec(),
+            }),
+            ..Default::default()
+        };
+        Block {
+            number,
+            hash: header_hash(&header).to_vec(),
+            header: Some(header),
+            ..Default::default()
+        }
+    }
+
+    /// writes `blocks` to an era1 file, returning it along with a master accumulator that
+    /// vouches for their epoch
+    fn write_era1(blocks: &[Block]) -> (Vec<u8>, PreMergeAccumulator) {
+        let headers: Vec<ExtHeaderRecord> = blocks
+            .iter()
+            .map(|block| ExtHeaderRecord::try_from(block).unwrap())
+            .collect();
+        let accumulator_root = epoch_accumulator_root(&headers);
+
+        let mut writer = Era1Writer::new(Vec::new()).unwrap();
+        for (block, header) in blocks.iter().zip(headers.iter()) {
+            writer.write_block(block, header).unwrap();
+        }
+        let bytes = writer.finish(accumulator_root).unwrap();
+
+        let macc = PreMergeAccumulator {
+            historical_epochs: vec![accumulator_root].into(),
+        };
+        (bytes, macc)
+    }
+
+    #[test]
+    fn written_era1_validates() {
+        let blocks: Vec<Block> = (0..MAX_EPOCH_SIZE as u64).map(block).collect();
+        let (bytes, macc) = write_era1(&blocks);
+
+        let era1 = Era1::from_bytes(&bytes).unwrap();
+        assert_eq!(era1.start_block, 0);
+        assert_eq!(era1.blocks.len(), MAX_EPOCH_SIZE);
+        assert_eq!(validate_era1(&era1, &macc).unwrap(), 0);
+
+        for (block, era1_block) in blocks.iter().zip(era1.blocks.iter()) {
+            assert_eq!(era1_block.total_difficulty, U256::from(block.number + 1));
+            assert!(compare_block(block, era1_block).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn tampered_total_difficulty_is_rejected() {
+        let blocks: Vec<Block> = (0..MAX_EPOCH_SIZE as u64).map(block).collect();
+        let (bytes, macc) = write_era1(&blocks);
+
+        let mut era1 = Era1::from_bytes(&bytes).unwrap();
+        era1.blocks[100].total_difficulty += U256::from(1);
+
+        assert!(matches!(
+            validate_era1(&era1, &macc),
+            Err(Era1Error::AccumulatorMismatch(0))
+        ));
+        assert_eq!(
+            compare_block(&blocks[100], &era1.blocks[100]).unwrap(),
+            vec![Era1Field::TotalDifficulty]
+        );
+    }
+
+    #[test]
+    fn partial_epoch_is_rejected() {
+        let blocks: Vec<Block> = (0..10).map(block).collect();
+        let (bytes, macc) = write_era1(&blocks);
+
+        let era1 = Era1::from_bytes(&bytes).unwrap();
+        assert!(matches!(
+            validate_era1(&era1, &macc),
+            Err(Era1Error::InvalidEra1(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_era1_is_rejected() {
+        let (bytes, _) = write_era1(&[block(0)]);
+        assert!(matches!(
+            Era1::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Era1Error::InvalidEra1(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreadable_bundles_are_recorded() {
+        let blocks: Vec<Block> = (0..3).map(block).collect();
+        let (bytes, _) = write_era1(&blocks);
+        let era1 = Era1::from_bytes(&bytes).unwrap();
+
+        let store = crate::store::new("memory://era1-unreadable-bundles", None).unwrap();
+        let comparison = compare_era1_with_store(&era1, &store).await.unwrap();
+
+        assert_eq!(comparison.compared, 0);
+        assert_eq!(comparison.missing_blocks, vec![0, 1, 2]);
+        assert_eq!(comparison.read_errors.len(), 1);
+        assert_eq!(comparison.read_errors[0].0, "0000000000.dbin.zst");
+        assert!(!comparison.is_consistent());
+    }
+
+    #[test]
+    fn fixture_epoch_validates_against_the_master_accumulator() {
+        let mut blocks = Vec::new();
+        for bundle_start in (0..MAX_EPOCH_SIZE).step_by(100) {
+            let path = format!(
+                "tests/ethereum_firehose_first_8200/{:010}.dbin",
+                bundle_start
+            );
+            blocks.extend(decode_bundle(&std::fs::read(path).unwrap()).unwrap());
+        }
+        blocks.retain(|block| (block.number as usize) < MAX_EPOCH_SIZE);
+
+        let headers: Vec<ExtHeaderRecord> = blocks
+            .iter()
+            .map(|block| ExtHeaderRecord::try_from(block).unwrap())
+            .collect();
+        let mut writer = Era1Writer::new(Vec::new()).unwrap();
+        for (block, header) in blocks.iter().zip(headers.iter()) {
+            writer.write_block(block, header).unwrap();
+        }
+        let bytes = writer.finish(epoch_accumulator_root(&headers)).unwrap();
+
+        let era1 = Era1::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            validate_era1(&era1, &PreMergeAccumulator::default()).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn era1_file_names_follow_the_accumulator_root() {
+        assert_eq!(
+            era1_file_name(0, &MAINNET_EPOCH_0_ROOT),
+            "mainnet-00000-5ec1ffb8.era1"
+        );
+        assert_eq!(
+            era1_file_name(FINAL_EPOCH, &B256::repeat_byte(0xab)),
+            "mainnet-01896-abababab.era1"
+        );
+    }
+
+    #[tokio::test]
+    async fn fixture_epoch_exports_to_the_mainnet_era1_file() {
+        let store = store_from_dir("tests/ethereum_firehose_first_8200")
+            .await
+            .unwrap();
+        let out_dir =
+            std::env::temp_dir().join(format!("flat-head-export-era1-{}", std::process::id()));
+
+        let path = export_era1(&store, &PreMergeAccumulator::default(), 0, &out_dir)
+            .await
+            .unwrap();
+        assert_eq!(path, out_dir.join("mainnet-00000-5ec1ffb8.era1"));
+
+        let era1 = Era1::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(era1.accumulator_root, MAINNET_EPOCH_0_ROOT);
+        assert_eq!(era1.blocks.len(), MAX_EPOCH_SIZE);
+
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -1,126 +1,793 @@
-use futures::stream::{FuturesOrdered, StreamExt};
-use tokio::task;
+use std::{collections::HashSet, fmt, ops::Range, path::PathBuf, sync::Arc};
 
-use header_accumulator::{era_validator::EraValidator, types::ExtHeaderRecord};
+use anyhow::Context;
+
+use futures::stream::{self, FuturesOrdered, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task};
+
+use header_accumulator::{
+    era_validator::EraValidator, errors::EraValidateError, types::ExtHeaderRecord,
+};
 use sf_protos::ethereum::r#type::v2::Block;
-use tokio::sync::mpsc;
 use trin_validation::accumulator::PreMergeAccumulator;
 
-use crate::store::{self, Store};
+use crate::{
+    cache::BundleCache,
+    checkpoint::{Checkpoint, CheckpointKey},
+    epoch_accumulator::{epoch_accumulator_root, export_epoch_accumulator},
+    headers::check_header_hash,
+    layout::BundleLayout,
+    metrics::metrics,
+    receipts::check_receipts_root,
+    store::{self, ReadError, RetryOptions, Store, StoreOptions},
+    transactions::check_transactions_root,
+};
 pub const MAX_EPOCH_SIZE: usize = 8192;
 pub const FINAL_EPOCH: usize = 1896;
 pub const MERGE_BLOCK: usize = 15537394;
 
+/// default number of epochs validated at the same time
+pub const DEFAULT_MAX_CONCURRENT_EPOCHS: usize = 4;
+/// default number of bundle files fetched from the store at the same time
+pub const DEFAULT_MAX_CONCURRENT_READS: usize = 16;
+
+/// Tuning options for [`verify_eras`].
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// how many epochs are validated at the same time. Each epoch in flight keeps its header records in memory.
+    pub max_concurrent_epochs: Option<usize>,
+    /// how many bundle files are fetched from the store at the same time, across all epochs.
+    pub max_concurrent_reads: Option<usize>,
+    /// rebuild each block's transactions root from its transaction traces and compare it with the header
+    pub check_transactions_root: bool,
+    /// rebuild each block's receipts root and logs bloom from its receipts and compare them with the header
+    pub check_receipts_root: bool,
+    /// directory to write the SSZ epoch accumulator of every valid epoch to
+    pub export_accumulators: Option<PathBuf>,
+    /// JSON file the outcome of every epoch is saved to as soon as the epoch is checked
+    pub checkpoint_file: Option<PathBuf>,
+    /// skip the epochs the checkpoint file records as valid. Failed and unfinished epochs are checked
+    /// again, and so is every epoch of a checkpoint written for another store or other checks
+    pub resume: bool,
+    /// neither read nor write the era validator's `lockfile.json`, so every epoch is checked in full
+    pub ignore_lock_file: bool,
+    /// retry policy for transient errors while reading bundle files
+    pub retry: RetryOptions,
+    /// local cache of bundle files, checked before reading the store
+    pub cache: Option<Arc<BundleCache>>,
+    /// how blocks are split into bundle files in the store, and where those files are
+    pub layout: BundleLayout,
+    /// configuration of the store, if it is a cloud store
+    pub store: StoreOptions,
+}
+
+impl VerifyOptions {
+    pub fn max_concurrent_epochs(&self) -> usize {
+        self.max_concurrent_epochs
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_EPOCHS)
+            .max(1)
+    }
+
+    pub fn max_concurrent_reads(&self) -> usize {
+        self.max_concurrent_reads
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_READS)
+            .max(1)
+    }
+}
+
+/// Overall outcome of validating a single epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EpochStatus {
+    /// The epoch accumulator matches the master accumulator.
+    Valid,
+    /// All headers were gathered but their accumulator does not match.
+    AccumulatorMismatch,
+    /// One or more bundle files could not be fetched from the store.
+    MissingBundles,
+    /// One or more bundle files could not be decoded into blocks.
+    DecodeFailures,
+    /// One or more blocks could not be converted into header records.
+    HeaderConversionFailures,
+    /// One or more headers do not hash to the hash stored for their block.
+    HeaderHashMismatch,
+    /// The headers are valid but some blocks' transactions do not match their transactions root.
+    TransactionsRootMismatch,
+    /// The headers are valid but some blocks' receipts do not match their receipts root or logs bloom.
+    ReceiptsRootMismatch,
+    /// The era validator rejected the headers for another reason.
+    ValidationError(String),
+}
+
+impl fmt::Display for EpochStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpochStatus::Valid => write!(f, "valid"),
+            EpochStatus::AccumulatorMismatch => write!(f, "accumulator mismatch"),
+            EpochStatus::MissingBundles => write!(f, "missing bundle files"),
+            EpochStatus::DecodeFailures => write!(f, "decode failures"),
+            EpochStatus::HeaderConversionFailures => write!(f, "header conversion failures"),
+            EpochStatus::HeaderHashMismatch => write!(f, "header hash mismatch"),
+            EpochStatus::TransactionsRootMismatch => write!(f, "transactions root mismatch"),
+            EpochStatus::ReceiptsRootMismatch => write!(f, "receipts root mismatch"),
+            EpochStatus::ValidationError(e) => write!(f, "validation error: {}", e),
+        }
+    }
+}
+
+/// A count of failures together with the bundle files they happened in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleFailures {
+    pub count: usize,
+    pub files: Vec<String>,
+}
+
+impl BundleFailures {
+    fn record(&mut self, file_name: &str, count: usize) {
+        self.count += count;
+        if !self.files.iter().any(|f| f == file_name) {
+            self.files.push(file_name.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// Validation report for a single epoch.
+///
+/// A report is produced for every requested epoch, so a failed epoch can be told
+/// apart from one that was never checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochReport {
+    pub epoch: usize,
+    pub status: EpochStatus,
+    /// bundle files that could not be fetched from the store, counted per file
+    pub missing_bundles: BundleFailures,
+    /// bundle files that were fetched but could not be decoded, counted per file
+    pub decode_failures: BundleFailures,
+    /// blocks that could not be converted into header records, counted per block
+    pub header_conversion_failures: BundleFailures,
+    /// blocks whose recomputed header hash differs from the stored one. They are left out of the accumulator
+    pub header_hash_mismatches: Vec<u64>,
+    /// blocks whose transaction traces do not match their transactions root, when checked
+    pub transactions_root_mismatches: Vec<u64>,
+    /// blocks whose receipts do not match their receipts root or logs bloom, when checked
+    pub receipts_root_mismatches: Vec<u64>,
+    /// file the epoch accumulator was written to, when exporting accumulators
+    pub exported_accumulator: Option<PathBuf>,
+    /// the epoch passed in an earlier run, according to the checkpoint file, and was not checked again
+    pub resumed: bool,
+}
+
+impl EpochReport {
+    fn new(epoch: usize) -> Self {
+        EpochReport {
+            epoch,
+            status: EpochStatus::Valid,
+            missing_bundles: BundleFailures::default(),
+            decode_failures: BundleFailures::default(),
+            header_conversion_failures: BundleFailures::default(),
+            header_hash_mismatches: Vec::new(),
+            transactions_root_mismatches: Vec::new(),
+            receipts_root_mismatches: Vec::new(),
+            exported_accumulator: None,
+            resumed: false,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.status == EpochStatus::Valid
+    }
+}
+
 /// verifies flat flies stored in directory against a header accumulator
 ///
+/// returns one [`EpochReport`] per requested epoch, ordered by epoch
 pub async fn verify_eras(
     store_url: String,
     macc: PreMergeAccumulator,
     compatible: Option<String>,
     start_epoch: usize,
     end_epoch: Option<usize>,
-    decompress: Option<bool>,
-) -> Result<Vec<usize>, anyhow::Error> {
-    let mut validated_epochs = Vec::new();
-    let (tx, mut rx) = mpsc::channel(5);
-
-    let blocks_store: store::Store = store::new(store_url, decompress.unwrap_or(false), compatible)
-        .expect("failed to create blocks store");
-
-    for epoch in start_epoch..=end_epoch.unwrap_or(start_epoch + 1) {
-        let tx = tx.clone();
-        let macc = macc.clone();
-        let store = blocks_store.clone();
-
-        task::spawn(async move {
-            match get_blocks_from_store(epoch, &store, decompress).await {
-                Ok(blocks) => {
-                    let (successful_headers, _): (Vec<_>, Vec<_>) = blocks
-                        .iter()
-                        .map(ExtHeaderRecord::try_from)
-                        .fold((Vec::new(), Vec::new()), |(mut succ, mut errs), res| {
-                            match res {
-                                Ok(header) => succ.push(header),
-                                Err(e) => {
-                                    // Log the error or handle it as needed
-                                    eprintln!("Error converting block: {:?}", e);
-                                    errs.push(e);
-                                }
-                            };
-                            (succ, errs)
-                        });
-
-                    let valid_epochs = macc
-                        .era_validate(successful_headers, epoch, Some(epoch + 1), true)
-                        .unwrap();
-
-                    let _ = tx.send(valid_epochs).await;
+    options: VerifyOptions,
+) -> Result<Vec<EpochReport>, anyhow::Error> {
+    let checkpoint_key = CheckpointKey {
+        store_url: store_url.clone(),
+        compatible: compatible.clone(),
+        check_transactions_root: options.check_transactions_root,
+        check_receipts_root: options.check_receipts_root,
+    };
+    let blocks_store: store::Store = store::new_with_options(store_url, compatible, &options.store)
+        .context("failed to create blocks store")?
+        .with_retry_options(options.retry.clone())
+        .with_cache(options.cache.clone())
+        .with_layout(options.layout.clone());
+
+    verify_epochs(
+        blocks_store,
+        checkpoint_key,
+        macc,
+        start_epoch,
+        end_epoch,
+        options,
+    )
+    .await
+}
+
+/// verifies the flat files of an already built store, such as one of
+/// [`store_from_dir`](crate::memory::store_from_dir), against a header accumulator
+///
+/// the store is used as it is: the retry, cache, layout and store options are left to the
+/// caller. Returns one [`EpochReport`] per requested epoch, ordered by epoch
+pub async fn verify_store_eras(
+    store: &Store,
+    macc: PreMergeAccumulator,
+    start_epoch: usize,
+    end_epoch: Option<usize>,
+    options: VerifyOptions,
+) -> Result<Vec<EpochReport>, anyhow::Error> {
+    let checkpoint_key = CheckpointKey {
+        store_url: store.url().to_string(),
+        compatible: None,
+        check_transactions_root: options.check_transactions_root,
+        check_receipts_root: options.check_receipts_root,
+    };
+
+    verify_epochs(
+        store.clone(),
+        checkpoint_key,
+        macc,
+        start_epoch,
+        end_epoch,
+        options,
+    )
+    .await
+}
+
+async fn verify_epochs(
+    blocks_store: Store,
+    checkpoint_key: CheckpointKey,
+    macc: PreMergeAccumulator,
+    start_epoch: usize,
+    end_epoch: Option<usize>,
+    options: VerifyOptions,
+) -> Result<Vec<EpochReport>, anyhow::Error> {
+    let end_epoch = end_epoch.unwrap_or(start_epoch + 1);
+    check_pre_merge_range(start_epoch, end_epoch)?;
+
+    // shared by every epoch, so the number of GETs in flight stays bounded no matter how many epochs run
+    let read_permits = Arc::new(Semaphore::new(options.max_concurrent_reads()));
+
+    let mut checkpoint = match &options.checkpoint_file {
+        Some(path) => Checkpoint::load_for(path, &checkpoint_key)
+            .with_context(|| format!("failed to load checkpoint file {}", path.display()))?,
+        None => Checkpoint::new(checkpoint_key),
+    };
+    let passed: HashSet<usize> = (start_epoch..=end_epoch)
+        .filter(|epoch| options.resume && checkpoint.passed(*epoch))
+        .collect();
+
+    let mut reports_stream = stream::iter(start_epoch..=end_epoch)
+        .map(|epoch| {
+            let resumed = passed.contains(&epoch);
+            let macc = macc.clone();
+            let store = blocks_store.clone();
+            let read_permits = read_permits.clone();
+            let options = options.clone();
+
+            async move {
+                if resumed {
+                    log::info!("Epoch {} passed in an earlier run, skipping", epoch);
+                    let mut report = EpochReport::new(epoch);
+                    report.resumed = true;
+                    return report;
                 }
-                Err(e) => eprintln!("Error fetching blocks for epoch {}: {:?}", epoch, e),
+
+                task::spawn(async move {
+                    let keep_headers = options.export_accumulators.is_some();
+                    let (mut report, headers) = validate_epoch(
+                        epoch,
+                        &store,
+                        &macc,
+                        &read_permits,
+                        &options,
+                        !options.ignore_lock_file,
+                        keep_headers,
+                    )
+                    .await;
+
+                    if let (Some(dir), Some(headers)) = (&options.export_accumulators, headers) {
+                        match export_epoch_accumulator(dir, epoch, &headers).await {
+                            Ok(path) => report.exported_accumulator = Some(path),
+                            Err(e) => {
+                                log::error!("Error exporting accumulator of epoch {}: {}", epoch, e)
+                            }
+                        }
+                    }
+
+                    report
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    let mut report = EpochReport::new(epoch);
+                    report.status =
+                        EpochStatus::ValidationError(format!("validation task failed: {}", e));
+                    report
+                })
+            }
+        })
+        // epochs finish in any order, and each one is checkpointed as soon as it does
+        .buffer_unordered(options.max_concurrent_epochs());
+
+    let mut reports = Vec::new();
+    while let Some(report) = reports_stream.next().await {
+        if !report.resumed {
+            if report.is_valid() {
+                metrics().epochs_validated.inc();
+            } else {
+                metrics().epochs_failed.inc();
+            }
+        }
+        if let (Some(path), false) = (&options.checkpoint_file, report.resumed) {
+            checkpoint.record(report.epoch, report.status.clone());
+            if let Err(e) = checkpoint.save(path) {
+                log::error!("Error saving checkpoint file {}: {}", path.display(), e);
             }
-        });
+        }
+        reports.push(report);
     }
+    reports.sort_by_key(|report| report.epoch);
 
-    // Drop the original sender to close the channel once all senders are dropped
-    drop(tx);
+    Ok(reports)
+}
 
-    // Process blocks as they arrive
-    while let Some(epochs) = rx.recv().await {
-        validated_epochs.extend(epochs);
+/// Checks an epoch of headers against the master accumulator.
+///
+/// The final pre-merge epoch is partial: it only holds the blocks below [`MERGE_BLOCK`].
+/// Its accumulator is computed from those headers alone and compared with the last
+/// historical epoch of the master accumulator.
+pub fn validate_epoch_headers(
+    macc: &PreMergeAccumulator,
+    headers: Vec<ExtHeaderRecord>,
+    epoch: usize,
+    use_lock: bool,
+) -> Result<(), EraValidateError> {
+    if epoch != FINAL_EPOCH {
+        return macc
+            .era_validate(headers, epoch, Some(epoch + 1), use_lock)
+            .map(|_| ());
     }
 
-    Ok(validated_epochs)
+    if headers.len() != MERGE_BLOCK - FINAL_EPOCH * MAX_EPOCH_SIZE {
+        return Err(EraValidateError::InvalidEpochLength);
+    }
+    if headers[0].block_number as usize != FINAL_EPOCH * MAX_EPOCH_SIZE {
+        return Err(EraValidateError::InvalidEpochStart);
+    }
+
+    if epoch_accumulator_root(&headers) != macc.historical_epochs[FINAL_EPOCH] {
+        log::error!("Era validation failed: {}", epoch);
+        return Err(EraValidateError::EraAccumulatorMismatch);
+    }
+
+    log::info!("Era validated successfully: {}", epoch);
+    Ok(())
+}
+
+/// fetches a single epoch from `store` and checks it against the master accumulator
+///
+/// the epoch's header records are returned along with the report when the epoch is valid.
+/// The era validator's lock file is not consulted, so the epoch is always checked in full
+/// before its headers are used to build proofs or era1 files.
+pub async fn validate_epoch_from_store(
+    epoch: usize,
+    store: &Store,
+    macc: &PreMergeAccumulator,
+    options: &VerifyOptions,
+) -> (EpochReport, Option<Vec<ExtHeaderRecord>>) {
+    let read_permits = Semaphore::new(options.max_concurrent_reads());
+    validate_epoch(epoch, store, macc, &read_permits, options, false, true).await
+}
+
+/// rejects empty epoch ranges, and ranges reaching past the merge, which have no pre-merge
+/// accumulator to check against
+fn check_pre_merge_range(start_epoch: usize, end_epoch: usize) -> Result<(), anyhow::Error> {
+    if start_epoch > end_epoch {
+        anyhow::bail!(
+            "start epoch {} is after end epoch {}: the range is inclusive and holds no epoch",
+            start_epoch,
+            end_epoch
+        );
+    }
+    if end_epoch > FINAL_EPOCH {
+        anyhow::bail!(
+            "epochs {}..={} reach past the merge: the last pre-merge epoch is {}, ending at block {}",
+            start_epoch,
+            end_epoch,
+            FINAL_EPOCH,
+            MERGE_BLOCK - 1
+        );
+    }
+    Ok(())
 }
 
-async fn get_blocks_from_store(
+/// `use_lock` lets the era validator skip epochs its lock file records as valid, and
+/// `keep_headers` returns the header records of a valid epoch instead of dropping them
+async fn validate_epoch(
     epoch: usize,
     store: &Store,
-    decompress: Option<bool>,
-) -> Result<Vec<Block>, anyhow::Error> {
-    let start_100_block = epoch * MAX_EPOCH_SIZE;
-    let end_100_block = (epoch + 1) * MAX_EPOCH_SIZE;
+    macc: &PreMergeAccumulator,
+    read_permits: &Semaphore,
+    options: &VerifyOptions,
+    use_lock: bool,
+    keep_headers: bool,
+) -> (EpochReport, Option<Vec<ExtHeaderRecord>>) {
+    let mut report = EpochReport::new(epoch);
+    let mut headers = Vec::with_capacity(MAX_EPOCH_SIZE);
+
+    let mut bundles = get_headers_from_store(epoch, store, read_permits, options);
+
+    while let Some((file_name, result)) = bundles.next().await {
+        match result {
+            Ok(bundle) => {
+                if bundle.conversion_failures > 0 {
+                    metrics()
+                        .header_conversion_errors
+                        .inc_by(bundle.conversion_failures as u64);
+                    report
+                        .header_conversion_failures
+                        .record(&file_name, bundle.conversion_failures);
+                }
+                report
+                    .header_hash_mismatches
+                    .extend(bundle.header_hash_mismatches);
+                report
+                    .transactions_root_mismatches
+                    .extend(bundle.transactions_root_mismatches);
+                report
+                    .receipts_root_mismatches
+                    .extend(bundle.receipts_root_mismatches);
+                headers.extend(bundle.headers);
+            }
+            Err(ReadError::DecodeError(e)) => {
+                log::error!("Error decoding {}: {}", file_name, e);
+                report.decode_failures.record(&file_name, 1);
+            }
+            Err(e) => {
+                log::error!("Error fetching {}: {}", file_name, e);
+                report.missing_bundles.record(&file_name, 1);
+            }
+        }
+    }
 
-    let mut blocks = extract_100s_blocks(store, start_100_block, end_100_block, decompress).await?;
+    report.status = if !report.missing_bundles.is_empty() {
+        EpochStatus::MissingBundles
+    } else if !report.decode_failures.is_empty() {
+        EpochStatus::DecodeFailures
+    } else if !report.header_conversion_failures.is_empty() {
+        EpochStatus::HeaderConversionFailures
+    } else if !report.header_hash_mismatches.is_empty() {
+        EpochStatus::HeaderHashMismatch
+    } else {
+        // the era validator takes the headers by value, so they are only copied when kept
+        let epoch_headers = if keep_headers {
+            headers.clone()
+        } else {
+            std::mem::take(&mut headers)
+        };
+        match validate_epoch_headers(macc, epoch_headers, epoch, use_lock) {
+            Ok(_) if !report.transactions_root_mismatches.is_empty() => {
+                EpochStatus::TransactionsRootMismatch
+            }
+            Ok(_) if !report.receipts_root_mismatches.is_empty() => {
+                EpochStatus::ReceiptsRootMismatch
+            }
+            Ok(_) => EpochStatus::Valid,
+            Err(EraValidateError::EraAccumulatorMismatch) => EpochStatus::AccumulatorMismatch,
+            Err(e) => EpochStatus::ValidationError(e.to_string()),
+        }
+    };
 
-    if epoch < FINAL_EPOCH {
-        blocks = blocks[0..MAX_EPOCH_SIZE].to_vec();
+    if report.is_valid() && keep_headers {
+        (report, Some(headers))
     } else {
-        blocks = blocks[0..MERGE_BLOCK].to_vec();
+        (report, None)
     }
+}
 
-    Ok(blocks)
+/// Header records taken from a single bundle file.
+struct BundleHeaders {
+    headers: Vec<ExtHeaderRecord>,
+    /// blocks of the bundle that could not be converted into header records
+    conversion_failures: usize,
+    /// blocks of the bundle whose header does not hash to the stored hash
+    header_hash_mismatches: Vec<u64>,
+    /// blocks of the bundle whose transactions do not match their transactions root
+    transactions_root_mismatches: Vec<u64>,
+    /// blocks of the bundle whose receipts do not match their receipts root or logs bloom
+    receipts_root_mismatches: Vec<u64>,
 }
 
-async fn extract_100s_blocks(
-    store: &Store,
+/// streams the header records of the bundles covering an epoch, keeping each bundle's file
+/// name next to its result
+fn get_headers_from_store<'a>(
+    epoch: usize,
+    store: &'a Store,
+    read_permits: &'a Semaphore,
+    options: &'a VerifyOptions,
+) -> impl Stream<Item = (String, Result<BundleHeaders, ReadError>)> + 'a {
+    let start_block = epoch * MAX_EPOCH_SIZE;
+    // the final pre-merge epoch stops right before the merge block
+    let end_block = ((epoch + 1) * MAX_EPOCH_SIZE).min(MERGE_BLOCK);
+
+    extract_bundle_headers(store, start_block, end_block, read_permits, options)
+}
+
+/// Bundles are turned into header records as soon as they are decoded, and their blocks are
+/// dropped right away. At most as many bundles as there are read permits are held in memory
+/// as full blocks.
+fn extract_bundle_headers<'a>(
+    store: &'a Store,
     start_block: usize,
     end_block: usize,
-    decompress: Option<bool>,
-) -> Result<Vec<Block>, anyhow::Error> {
-    // Flat files are stored in 100 block files
-    // So we need to find the 100 block file that contains the start block and the 100 block file that contains the end block
-    let start_100_block = (start_block / 100) * 100;
-    let end_100_block = (((end_block as f32) / 100.0).ceil() as usize) * 100;
+    read_permits: &'a Semaphore,
+    options: &'a VerifyOptions,
+) -> impl Stream<Item = (String, Result<BundleHeaders, ReadError>)> + 'a {
+    // Flat files are stored in bundles of blocks, so read every bundle holding a block of the range
+    let mut futs = FuturesOrdered::new();
 
-    let zst_extension = if decompress.unwrap() { ".zst" } else { "" };
+    for bundle_start in store
+        .layout()
+        .bundle_starts(start_block as u64, end_block as u64)
+    {
+        futs.push_back(async move {
+            // the permit is held until the blocks are dropped, bounding how many bundles are decoded at once
+            let _permit = read_permits
+                .acquire()
+                .await
+                .expect("read semaphore is never closed");
+            let (block_file_name, result) = store.read_bundle(bundle_start).await;
+            let result =
+                result.map(|blocks| bundle_headers(&blocks, start_block..end_block, options));
+            (block_file_name, result)
+        })
+    }
 
-    let mut futs = FuturesOrdered::new();
+    futs
+}
+
+fn bundle_headers(blocks: &[Block], range: Range<usize>, options: &VerifyOptions) -> BundleHeaders {
+    let mut bundle = BundleHeaders {
+        headers: Vec::with_capacity(blocks.len()),
+        conversion_failures: 0,
+        header_hash_mismatches: Vec::new(),
+        transactions_root_mismatches: Vec::new(),
+        receipts_root_mismatches: Vec::new(),
+    };
+
+    // Keep only the requested blocks
+    for block in blocks
+        .iter()
+        .filter(|block| range.contains(&(block.number as usize)))
+    {
+        if options.check_transactions_root {
+            if let Err(e) = check_transactions_root(block) {
+                log::error!(
+                    "Error checking transactions of block {}: {}",
+                    block.number,
+                    e
+                );
+                bundle.transactions_root_mismatches.push(block.number);
+            }
+        }
+
+        if options.check_receipts_root {
+            if let Err(e) = check_receipts_root(block) {
+                log::error!("Error checking receipts of block {}: {}", block.number, e);
+                bundle.receipts_root_mismatches.push(block.number);
+            }
+        }
 
-    for block_number in (start_100_block..end_100_block).step_by(100) {
-        let block_file_name = format!("{:010}.dbin{}", block_number, zst_extension);
-        futs.push_back(store.read_blocks(block_file_name))
+        // the stored hash is what the accumulator checks, so make sure the header fields back it up
+        if let Err(e) = check_header_hash(block) {
+            log::error!("Error checking header of block {}: {}", block.number, e);
+            bundle.header_hash_mismatches.push(block.number);
+            continue;
+        }
+
+        match ExtHeaderRecord::try_from(block) {
+            Ok(header) => bundle.headers.push(header),
+            Err(e) => {
+                log::error!("Error converting block {}: {:?}", block.number, e);
+                bundle.conversion_failures += 1;
+            }
+        }
     }
 
-    let mut blocks_join = Vec::new();
+    bundle
+}
 
-    while let Some(res) = futs.next().await {
-        match res {
-            Ok(blocks) => blocks_join.extend(blocks),
-            Err(e) => println!("{:?}", e),
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{B256, U256};
+    use object_store::{path::Path, ObjectStore};
+
+    use super::*;
+    use crate::memory::{load_dir, named_memory_store, store_from_dir};
+
+    const FIXTURE_DIR: &str = "tests/ethereum_firehose_first_8200";
+
+    fn options() -> VerifyOptions {
+        VerifyOptions {
+            ignore_lock_file: true,
+            ..Default::default()
         }
     }
 
-    // Return only the requested blocks
+    #[tokio::test]
+    async fn fixture_epoch_validates() {
+        let reports = verify_eras(
+            FIXTURE_DIR.to_string(),
+            PreMergeAccumulator::default(),
+            None,
+            0,
+            Some(0),
+            options(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].epoch, 0);
+        assert_eq!(reports[0].status, EpochStatus::Valid);
+    }
+
+    #[tokio::test]
+    async fn fixture_store_validates() {
+        let store = store_from_dir(FIXTURE_DIR).await.unwrap();
+        let reports = verify_store_eras(
+            &store,
+            PreMergeAccumulator::default(),
+            0,
+            Some(0),
+            options(),
+        )
+        .await
+        .unwrap();
 
-    Ok(blocks_join[start_block - start_100_block..end_block - start_100_block].to_vec())
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].epoch, 0);
+        assert_eq!(reports[0].status, EpochStatus::Valid);
+    }
+
+    #[tokio::test]
+    async fn store_with_an_empty_range_is_refused() {
+        let store = store_from_dir(FIXTURE_DIR).await.unwrap();
+        assert!(verify_store_eras(
+            &store,
+            PreMergeAccumulator::default(),
+            1,
+            Some(0),
+            options()
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn tampered_bundle_fails_its_epoch() {
+        let store = named_memory_store("era-verifier-tampered");
+        load_dir(store.as_ref(), FIXTURE_DIR, &Path::default())
+            .await
+            .unwrap();
+        // blocks 100 to 199 in place of blocks 200 to 299
+        let other_bundle = std::fs::read(format!("{}/0000000100.dbin", FIXTURE_DIR)).unwrap();
+        store
+            .put(&Path::from("0000000200.dbin"), other_bundle.into())
+            .await
+            .unwrap();
+
+        let reports = verify_eras(
+            "memory://era-verifier-tampered".to_string(),
+            PreMergeAccumulator::default(),
+            None,
+            0,
+            Some(0),
+            options(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(reports.len(), 1);
+        assert!(!reports[0].is_valid());
+    }
+
+    /// header records of the partial final epoch, from its first block up to the merge
+    fn final_epoch_headers() -> Vec<ExtHeaderRecord> {
+        (FINAL_EPOCH * MAX_EPOCH_SIZE..MERGE_BLOCK)
+            .map(|block_number| ExtHeaderRecord {
+                block_hash: B256::left_padding_from(&block_number.to_be_bytes()),
+                total_difficulty: U256::from(block_number),
+                block_number: block_number as u64,
+            })
+            .collect()
+    }
+
+    /// master accumulator whose final epoch root is the root of `headers`
+    fn final_epoch_macc(headers: &[ExtHeaderRecord]) -> PreMergeAccumulator {
+        let mut historical_epochs = vec![B256::ZERO; FINAL_EPOCH + 1];
+        historical_epochs[FINAL_EPOCH] = epoch_accumulator_root(headers);
+        PreMergeAccumulator {
+            historical_epochs: historical_epochs.into(),
+        }
+    }
+
+    #[test]
+    fn partial_final_epoch_validates() {
+        let headers = final_epoch_headers();
+        assert_eq!(headers.len(), 5362);
+        let macc = final_epoch_macc(&headers);
+
+        validate_epoch_headers(&macc, headers.clone(), FINAL_EPOCH, false).unwrap();
+
+        let mut tampered = headers.clone();
+        tampered[100].total_difficulty += U256::from(1);
+        assert!(matches!(
+            validate_epoch_headers(&macc, tampered, FINAL_EPOCH, false),
+            Err(EraValidateError::EraAccumulatorMismatch)
+        ));
+
+        let mut short = headers.clone();
+        short.pop();
+        assert!(matches!(
+            validate_epoch_headers(&macc, short, FINAL_EPOCH, false),
+            Err(EraValidateError::InvalidEpochLength)
+        ));
+
+        let mut shifted = headers;
+        shifted.remove(0);
+        shifted.push(shifted[0].clone());
+        assert!(matches!(
+            validate_epoch_headers(&macc, shifted, FINAL_EPOCH, false),
+            Err(EraValidateError::InvalidEpochStart)
+        ));
+    }
+
+    #[test]
+    fn epoch_ranges_are_checked() {
+        check_pre_merge_range(0, 0).unwrap();
+        check_pre_merge_range(3, FINAL_EPOCH).unwrap();
+        assert!(check_pre_merge_range(5, 0).is_err());
+        assert!(check_pre_merge_range(FINAL_EPOCH, FINAL_EPOCH + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn inverted_range_is_an_error() {
+        let result = verify_eras(
+            FIXTURE_DIR.to_string(),
+            PreMergeAccumulator::default(),
+            None,
+            5,
+            Some(0),
+            options(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_store_url_is_an_error() {
+        let result = verify_eras(
+            "unknown://store".to_string(),
+            PreMergeAccumulator::default(),
+            None,
+            0,
+            Some(0),
+            options(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,216 @@
+use alloy_primitives::{keccak256, B256};
+use alloy_rlp::{Decodable, Encodable, Header};
+use thiserror::Error;
+
+use sf_protos::ethereum::r#type::v2::{Block, BlockHeader};
+
+use crate::{transactions::encode_big_int, utils::read_hash};
+
+/// first block with a base fee in its header
+pub const LONDON_BLOCK: u64 = 12_965_000;
+/// first block with a withdrawals root in its header
+pub const SHANGHAI_BLOCK: u64 = 17_034_870;
+/// first block with blob gas fields and a parent beacon block root in its header
+pub const CANCUN_BLOCK: u64 = 19_426_587;
+/// first block with a requests hash in its header, which firehose headers do not carry
+pub const PRAGUE_BLOCK: u64 = 22_431_084;
+
+#[derive(Error, Debug)]
+pub enum HeaderError {
+    #[error("Block {0} has no header")]
+    MissingHeader(u64),
+    #[error("Block {block}: header hashes to {computed} but {stored} is stored")]
+    HashMismatch {
+        block: u64,
+        stored: B256,
+        computed: B256,
+    },
+    #[error("{field} is {len} bytes long instead of 32")]
+    InvalidHash { field: &'static str, len: usize },
+    #[error("Block {0} is from Prague or later, whose header hash cannot be rebuilt without its requests hash")]
+    UnsupportedFork(u64),
+}
+
+/// RLP-encodes a block header, including the fields each fork added up to Cancun.
+///
+/// Headers from Prague on also commit to a requests hash, which firehose headers do not
+/// carry, so their encoding does not hash to the block hash.
+pub fn encode_header(header: &BlockHeader) -> Vec<u8> {
+    let mut payload = Vec::new();
+
+    header.parent_hash.as_slice().encode(&mut payload);
+    header.uncle_hash.as_slice().encode(&mut payload);
+    header.coinbase.as_slice().encode(&mut payload);
+    header.state_root.as_slice().encode(&mut payload);
+    header.transactions_root.as_slice().encode(&mut payload);
+    header.receipt_root.as_slice().encode(&mut payload);
+    header.logs_bloom.as_slice().encode(&mut payload);
+    encode_big_int(&header.difficulty, &mut payload);
+    header.number.encode(&mut payload);
+    header.gas_limit.encode(&mut payload);
+    header.gas_used.encode(&mut payload);
+    header
+        .timestamp
+        .as_ref()
+        .map_or(0, |timestamp| timestamp.seconds as u64)
+        .encode(&mut payload);
+    header.extra_data.as_slice().encode(&mut payload);
+    header.mix_hash.as_slice().encode(&mut payload);
+    // the nonce is a fixed 8 byte string rather than an integer
+    header.nonce.to_be_bytes().as_slice().encode(&mut payload);
+
+    if header.number >= LONDON_BLOCK {
+        encode_big_int(&header.base_fee_per_gas, &mut payload);
+    }
+    if header.number >= SHANGHAI_BLOCK {
+        header.withdrawals_root.as_slice().encode(&mut payload);
+    }
+    if header.number >= CANCUN_BLOCK {
+        header
+            .blob_gas_used
+            .unwrap_or_default()
+            .encode(&mut payload);
+        header
+            .excess_blob_gas
+            .unwrap_or_default()
+            .encode(&mut payload);
+        header.parent_beacon_root.as_slice().encode(&mut payload);
+    }
+
+    let mut out = Vec::with_capacity(payload.len() + 3);
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&payload);
+    out
+}
+
+/// keccak of the RLP-encoded header
+pub fn header_hash(header: &BlockHeader) -> B256 {
+    keccak256(encode_header(header))
+}
+
+/// hash firehose stored for the block
+pub fn block_hash(block: &Block) -> Result<B256, HeaderError> {
+    read_hash(&block.hash).map_err(|len| HeaderError::InvalidHash {
+        field: "Block hash",
+        len,
+    })
+}
+
+/// reads the block number of an RLP-encoded header, its ninth field
+pub fn header_number(header: &[u8]) -> Result<u64, alloy_rlp::Error> {
+    let mut fields = Header::decode_bytes(&mut &header[..], true)?;
+    for _ in 0..8 {
+        let field = Header::decode(&mut fields)?;
+        if field.payload_length > fields.len() {
+            return Err(alloy_rlp::Error::InputTooShort);
+        }
+        fields = &fields[field.payload_length..];
+    }
+
+    u64::decode(&mut fields)
+}
+
+/// recomputes the block's header hash and compares it with the hash firehose stored for the block
+pub fn check_header_hash(block: &Block) -> Result<(), HeaderError> {
+    let header = block
+        .header
+        .as_ref()
+        .ok_or(HeaderError::MissingHeader(block.number))?;
+    if header.number >= PRAGUE_BLOCK {
+        return Err(HeaderError::UnsupportedFork(header.number));
+    }
+
+    let stored = block_hash(block)?;
+    let computed = header_hash(header);
+
+    if computed != stored {
+        return Err(HeaderError::HashMismatch {
+            block: block.number,
+            stored,
+            computed,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::b256;
+
+    use super::*;
+    use crate::store::decode_bundle;
+
+    const GENESIS_HASH: B256 =
+        b256!("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3");
+
+    fn block(number: u64, hash: Vec<u8>) -> Block {
+        Block {
+            number,
+            hash,
+            header: Some(BlockHeader {
+                number,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fixture_header_hashes() {
+        let bytes = std::fs::read("tests/ethereum_firehose_first_8200/0000000000.dbin").unwrap();
+        let blocks = decode_bundle(&bytes).unwrap();
+        assert_eq!(blocks.len(), 100);
+
+        assert_eq!(
+            header_hash(blocks[0].header.as_ref().unwrap()),
+            GENESIS_HASH
+        );
+        for block in blocks.iter() {
+            check_header_hash(block).unwrap();
+            let encoded = encode_header(block.header.as_ref().unwrap());
+            assert_eq!(header_number(&encoded).unwrap(), block.number);
+        }
+    }
+
+    #[test]
+    fn header_number_reads_ninth_field() {
+        let encoded = encode_header(&BlockHeader {
+            number: 1_234_567,
+            logs_bloom: vec![0; 256],
+            ..Default::default()
+        });
+        assert_eq!(header_number(&encoded).unwrap(), 1_234_567);
+    }
+
+    #[test]
+    fn hash_mismatch_is_reported() {
+        let block = block(1, vec![0xaa; 32]);
+        assert!(matches!(
+            check_header_hash(&block),
+            Err(HeaderError::HashMismatch { block: 1, stored, .. }) if stored == B256::repeat_byte(0xaa)
+        ));
+    }
+
+    #[test]
+    fn oversized_hash_is_an_error() {
+        let block = block(1, vec![0xaa; 33]);
+        assert!(matches!(
+            check_header_hash(&block),
+            Err(HeaderError::InvalidHash { len: 33, .. })
+        ));
+    }
+
+    #[test]
+    fn prague_headers_are_not_checked() {
+        let block = block(PRAGUE_BLOCK, vec![0xaa; 32]);
+        assert!(matches!(
+            check_header_hash(&block),
+            Err(HeaderError::UnsupportedFork(PRAGUE_BLOCK))
+        ));
+    }
+}
@@ -0,0 +1,468 @@
+use std::path::{Path, PathBuf};
+
+use alloy_rlp::Decodable;
+use ssz::Decode;
+use thiserror::Error;
+
+use ethportal_api::types::execution::{
+    header::Header,
+    header_with_proof::{AccumulatorProof, BlockHeaderProof, HeaderWithProof},
+};
+use header_accumulator::types::ExtHeaderRecord;
+use sf_protos::ethereum::r#type::v2::Block;
+use trin_validation::{
+    accumulator::PreMergeAccumulator, header_validator::HeaderValidator,
+    historical_roots_acc::HistoricalRootsAccumulator,
+};
+
+use crate::{
+    epoch_accumulator::epoch_accumulator,
+    era_verifier::{
+        validate_epoch_from_store, EpochStatus, VerifyOptions, MAX_EPOCH_SIZE, MERGE_BLOCK,
+    },
+    headers::{encode_header, HeaderError},
+    store::{decode_bundle, ReadError, Store},
+};
+
+/// number of roots in the proof of a block hash inside an epoch accumulator: one level inside
+/// its header record, 13 levels for the 8192 header records and one for the list length
+pub const EPOCH_ACCUMULATOR_PROOF_DEPTH: usize = 15;
+
+#[derive(Error, Debug)]
+pub enum ProofError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("IO error reading {path}: {source}")]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Block {0} is after the merge and is not part of the master accumulator")]
+    PostMergeBlock(u64),
+    #[error("Epoch {epoch} did not validate: {status}")]
+    InvalidEpoch { epoch: usize, status: EpochStatus },
+    #[error("Block {0} was not found")]
+    MissingBlock(u64),
+    #[error("Read error: {0}")]
+    Read(#[from] ReadError),
+    #[error("Header error: {0}")]
+    Header(#[from] HeaderError),
+    #[error("Proof of block {block} could not be built: {reason}")]
+    Construction { block: u64, reason: String },
+    #[error("Proof of block {block} does not match the master accumulator: {reason}")]
+    ProofMismatch { block: u64, reason: String },
+    #[error("Invalid SSZ encoding: {0}")]
+    InvalidSsz(String),
+    #[error("Invalid RLP header: {0}")]
+    InvalidHeader(String),
+    #[error("Header does not match the header bundled with its proof")]
+    HeaderMismatch,
+    #[error("A bare proof needs a header file or a flat file to check")]
+    MissingHeader,
+}
+
+/// decodes an RLP-encoded header, which must not be followed by any other bytes
+pub fn decode_header(header: &[u8]) -> Result<Header, ProofError> {
+    let mut bytes = header;
+    let decoded =
+        Header::decode(&mut bytes).map_err(|e| ProofError::InvalidHeader(e.to_string()))?;
+    if !bytes.is_empty() {
+        return Err(ProofError::InvalidHeader(format!(
+            "{} bytes follow the header",
+            bytes.len()
+        )));
+    }
+    Ok(decoded)
+}
+
+/// header of a firehose block, as a Portal Network header
+pub fn block_header(block: &Block) -> Result<Header, ProofError> {
+    let header = block
+        .header
+        .as_ref()
+        .ok_or(HeaderError::MissingHeader(block.number))?;
+    decode_header(&encode_header(header))
+}
+
+/// decodes an SSZ `HeaderWithProof`
+pub fn header_with_proof_from_ssz_bytes(bytes: &[u8]) -> Result<HeaderWithProof, ProofError> {
+    HeaderWithProof::from_ssz_bytes(bytes).map_err(|e| ProofError::InvalidSsz(format!("{:?}", e)))
+}
+
+/// decodes a bare SSZ `AccumulatorProof`, a vector of 15 roots
+pub fn accumulator_proof_from_ssz_bytes(bytes: &[u8]) -> Result<AccumulatorProof, ProofError> {
+    AccumulatorProof::from_ssz_bytes(bytes).map_err(|e| ProofError::InvalidSsz(format!("{:?}", e)))
+}
+
+pub fn header_with_proof_from_file(path: impl AsRef<Path>) -> Result<HeaderWithProof, ProofError> {
+    header_with_proof_from_ssz_bytes(&std::fs::read(path)?)
+}
+
+/// checks a `HeaderWithProof` against the master accumulator, returning its block number
+pub fn verify_header_with_proof(
+    macc: &PreMergeAccumulator,
+    header_with_proof: &HeaderWithProof,
+) -> Result<u64, ProofError> {
+    let block_number = header_with_proof.header.number;
+    if block_number >= MERGE_BLOCK as u64 {
+        return Err(ProofError::PostMergeBlock(block_number));
+    }
+
+    let validator = HeaderValidator {
+        pre_merge_acc: macc.clone(),
+        historical_roots_acc: HistoricalRootsAccumulator::default(),
+    };
+    validator
+        .validate_header_with_proof(header_with_proof)
+        .map_err(|e| ProofError::ProofMismatch {
+            block: block_number,
+            reason: e.to_string(),
+        })?;
+
+    Ok(block_number)
+}
+
+/// checks a header and its accumulator proof against the master accumulator, returning its
+/// block number
+pub fn verify_header_proof(
+    macc: &PreMergeAccumulator,
+    header: &Header,
+    proof: &AccumulatorProof,
+) -> Result<u64, ProofError> {
+    verify_header_with_proof(
+        macc,
+        &HeaderWithProof {
+            header: header.clone(),
+            proof: BlockHeaderProof::AccumulatorProof(proof.clone()),
+        },
+    )
+}
+
+/// checks the header of a firehose block and its proof against the master accumulator
+pub fn verify_block_proof(
+    macc: &PreMergeAccumulator,
+    block: &Block,
+    proof: &AccumulatorProof,
+) -> Result<(), ProofError> {
+    verify_header_proof(macc, &block_header(block)?, proof).map(|_| ())
+}
+
+/// checks the proof in `proof_file` against the master accumulator, returning the proven block.
+///
+/// The proof file holds either a `HeaderWithProof` or a bare accumulator proof. The header is
+/// read from `flat_file`, the block of the given number inside a flat file, or from
+/// `header_file`, an RLP-encoded header, or else from the proof file itself. A header read
+/// from another file must match the one bundled with the proof, if any.
+pub fn verify_proof_file(
+    macc: &PreMergeAccumulator,
+    proof_file: &Path,
+    header_file: Option<&Path>,
+    flat_file: Option<(&Path, u64)>,
+) -> Result<u64, ProofError> {
+    let proof_bytes = read_file(proof_file)?;
+
+    // a bare proof has a fixed size, which a `HeaderWithProof` can never have
+    let (bundled_header, proof) = if proof_bytes.len() == EPOCH_ACCUMULATOR_PROOF_DEPTH * 32 {
+        let proof = accumulator_proof_from_ssz_bytes(&proof_bytes)?;
+        (None, BlockHeaderProof::AccumulatorProof(proof))
+    } else {
+        let header_with_proof = header_with_proof_from_ssz_bytes(&proof_bytes)?;
+        (Some(header_with_proof.header), header_with_proof.proof)
+    };
+
+    let header = match (flat_file, header_file) {
+        (Some((flat_file, block_number)), _) => {
+            let block = decode_bundle(&read_file(flat_file)?)?
+                .into_iter()
+                .find(|block| block.number == block_number)
+                .ok_or(ProofError::MissingBlock(block_number))?;
+            block_header(&block)?
+        }
+        (None, Some(header_file)) => decode_header(&read_file(header_file)?)?,
+        (None, None) => bundled_header.clone().ok_or(ProofError::MissingHeader)?,
+    };
+
+    if bundled_header.is_some_and(|bundled_header| bundled_header != header) {
+        return Err(ProofError::HeaderMismatch);
+    }
+
+    verify_header_with_proof(macc, &HeaderWithProof { header, proof })
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, ProofError> {
+    std::fs::read(path).map_err(|source| ProofError::File {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// builds the `HeaderWithProof` of `header` from the header records of its whole epoch
+pub fn build_header_with_proof(
+    headers: &[ExtHeaderRecord],
+    header: Header,
+) -> Result<HeaderWithProof, ProofError> {
+    let proof = PreMergeAccumulator::construct_proof(&header, &epoch_accumulator(headers))
+        .map_err(|e| ProofError::Construction {
+            block: header.number,
+            reason: e.to_string(),
+        })?;
+
+    Ok(HeaderWithProof {
+        header,
+        proof: BlockHeaderProof::AccumulatorProof(AccumulatorProof { proof }),
+    })
+}
+
+/// builds the inclusion proof of a pre-merge block from the flat files in `store`.
+///
+/// The whole epoch of the block is fetched and validated against the master accumulator
+/// first, so a proof is only ever built from headers the accumulator vouches for.
+pub async fn prove_block(
+    store: &Store,
+    macc: &PreMergeAccumulator,
+    block_number: u64,
+) -> Result<HeaderWithProof, ProofError> {
+    if block_number >= MERGE_BLOCK as u64 {
+        return Err(ProofError::PostMergeBlock(block_number));
+    }
+
+    let epoch = block_number as usize / MAX_EPOCH_SIZE;
+    let (report, headers) =
+        validate_epoch_from_store(epoch, store, macc, &VerifyOptions::default()).await;
+    let headers = headers.ok_or(ProofError::InvalidEpoch {
+        epoch,
+        status: report.status,
+    })?;
+
+    let index = block_number as usize % MAX_EPOCH_SIZE;
+    if headers.get(index).map(|header| header.block_number) != Some(block_number) {
+        return Err(ProofError::MissingBlock(block_number));
+    }
+
+    let (_, bundle) = store
+        .read_bundle(store.layout().bundle_start(block_number))
+        .await;
+    let block = bundle?
+        .into_iter()
+        .find(|block| block.number == block_number)
+        .ok_or(ProofError::MissingBlock(block_number))?;
+
+    let header_with_proof = build_header_with_proof(&headers, block_header(&block)?)?;
+
+    // the bundle was read a second time, so check the header itself against the master accumulator
+    verify_header_with_proof(macc, &header_with_proof)?;
+
+    Ok(header_with_proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use sf_protos::ethereum::r#type::v2::{BigInt, BlockHeader};
+    use ssz::Encode;
+
+    use super::*;
+    use crate::{
+        epoch_accumulator::epoch_accumulator_root, headers::header_hash, memory::store_from_dir,
+    };
+
+    fn block(number: u64) -> Block {
+        let header = BlockHeader {
+            number,
+            logs_bloom: vec![0; 256],
+            difficulty: Some(BigInt { bytes: vec![1] }),
+            total_difficulty: Some(BigInt {
+                bytes: vec![number as u8 + 1],
+            }),
+            ..Default::default()
+        };
+        Block {
+            number,
+            hash: header_hash(&header).to_vec(),
+            header: Some(header),
+            ..Default::default()
+        }
+    }
+
+    /// the first blocks of epoch 0, and a master accumulator holding the root of just those
+    fn epoch(count: u64) -> (Vec<Block>, Vec<ExtHeaderRecord>, PreMergeAccumulator) {
+        let blocks: Vec<Block> = (0..count).map(block).collect();
+        let headers: Vec<ExtHeaderRecord> = blocks
+            .iter()
+            .map(|block| ExtHeaderRecord::try_from(block).unwrap())
+            .collect();
+        let macc = PreMergeAccumulator {
+            historical_epochs: vec![epoch_accumulator_root(&headers)].into(),
+        };
+        (blocks, headers, macc)
+    }
+
+    #[test]
+    fn proof_round_trip() {
+        let (blocks, headers, macc) = epoch(10);
+
+        for block in blocks.iter() {
+            let header_with_proof =
+                build_header_with_proof(&headers, block_header(block).unwrap()).unwrap();
+            let decoded =
+                header_with_proof_from_ssz_bytes(&header_with_proof.as_ssz_bytes()).unwrap();
+            assert_eq!(decoded, header_with_proof);
+            assert_eq!(
+                verify_header_with_proof(&macc, &decoded).unwrap(),
+                block.number
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let (blocks, headers, macc) = epoch(10);
+        let mut header_with_proof =
+            build_header_with_proof(&headers, block_header(&blocks[3]).unwrap()).unwrap();
+
+        let BlockHeaderProof::AccumulatorProof(proof) = &mut header_with_proof.proof else {
+            panic!("not an accumulator proof");
+        };
+        proof.proof[5].0[0] ^= 1;
+
+        assert!(matches!(
+            verify_header_with_proof(&macc, &header_with_proof),
+            Err(ProofError::ProofMismatch { block: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn proof_of_another_block_is_rejected() {
+        let (blocks, headers, macc) = epoch(10);
+        let header_with_proof =
+            build_header_with_proof(&headers, block_header(&blocks[3]).unwrap()).unwrap();
+        let BlockHeaderProof::AccumulatorProof(proof) = &header_with_proof.proof else {
+            panic!("not an accumulator proof");
+        };
+
+        assert!(verify_block_proof(&macc, &blocks[3], proof).is_ok());
+        assert!(matches!(
+            verify_block_proof(&macc, &blocks[4], proof),
+            Err(ProofError::ProofMismatch { block: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn post_merge_header_is_rejected() {
+        let (_, _, macc) = epoch(1);
+        let header = block_header(&block(MERGE_BLOCK as u64)).unwrap();
+        let proof = AccumulatorProof {
+            proof: Default::default(),
+        };
+
+        assert!(matches!(
+            verify_header_proof(&macc, &header, &proof),
+            Err(ProofError::PostMergeBlock(_))
+        ));
+    }
+
+    #[test]
+    fn trailing_header_bytes_are_rejected() {
+        let mut header = encode_header(block(1).header.as_ref().unwrap());
+        header.push(0);
+        assert!(matches!(
+            decode_header(&header),
+            Err(ProofError::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn proof_files_with_rlp_headers() {
+        let dir =
+            std::env::temp_dir().join(format!("flat-head-proof-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (blocks, headers, macc) = epoch(10);
+
+        let header_with_proof =
+            build_header_with_proof(&headers, block_header(&blocks[3]).unwrap()).unwrap();
+        let BlockHeaderProof::AccumulatorProof(proof) = &header_with_proof.proof else {
+            panic!("not an accumulator proof");
+        };
+        let file = |name: &str, bytes: Vec<u8>| {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            path
+        };
+        let with_header = file("header_with_proof.ssz", header_with_proof.as_ssz_bytes());
+        let bare = file("proof.ssz", proof.as_ssz_bytes());
+        let header = file(
+            "header.rlp",
+            encode_header(blocks[3].header.as_ref().unwrap()),
+        );
+        let other_header = file(
+            "other.rlp",
+            encode_header(blocks[4].header.as_ref().unwrap()),
+        );
+
+        assert_eq!(
+            verify_proof_file(&macc, &with_header, None, None).unwrap(),
+            3
+        );
+        assert_eq!(
+            verify_proof_file(&macc, &bare, Some(&header), None).unwrap(),
+            3
+        );
+        assert_eq!(
+            verify_proof_file(&macc, &with_header, Some(&header), None).unwrap(),
+            3
+        );
+
+        assert!(matches!(
+            verify_proof_file(&macc, &bare, None, None),
+            Err(ProofError::MissingHeader)
+        ));
+        assert!(matches!(
+            verify_proof_file(&macc, &with_header, Some(&other_header), None),
+            Err(ProofError::HeaderMismatch)
+        ));
+        assert!(matches!(
+            verify_proof_file(&macc, &bare, Some(&other_header), None),
+            Err(ProofError::ProofMismatch { block: 4, .. })
+        ));
+        assert!(matches!(
+            verify_proof_file(&macc, &dir.join("missing.ssz"), None, None),
+            Err(ProofError::File { .. })
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn proof_files_with_flat_files() {
+        const FIXTURE_DIR: &str = "tests/ethereum_firehose_first_8200";
+        let dir = std::env::temp_dir().join(format!("flat-head-flat-proof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let macc = PreMergeAccumulator::default();
+
+        let store = store_from_dir(FIXTURE_DIR).await.unwrap();
+        let header_with_proof = prove_block(&store, &macc, 150).await.unwrap();
+        let BlockHeaderProof::AccumulatorProof(proof) = &header_with_proof.proof else {
+            panic!("not an accumulator proof");
+        };
+        let bare = dir.join("proof.ssz");
+        std::fs::write(&bare, proof.as_ssz_bytes()).unwrap();
+
+        let flat_file = Path::new(FIXTURE_DIR).join("0000000100.dbin");
+        assert_eq!(
+            verify_proof_file(&macc, &bare, None, Some((&flat_file, 150))).unwrap(),
+            150
+        );
+        // the flat file is preferred over the header bundled with a proof
+        let with_header = dir.join("header_with_proof.ssz");
+        std::fs::write(&with_header, header_with_proof.as_ssz_bytes()).unwrap();
+        assert!(matches!(
+            verify_proof_file(&macc, &with_header, None, Some((&flat_file, 151))),
+            Err(ProofError::HeaderMismatch)
+        ));
+        assert!(matches!(
+            verify_proof_file(&macc, &bare, None, Some((&flat_file, 250))),
+            Err(ProofError::MissingBlock(250))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
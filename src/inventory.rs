@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+
+use crate::store::{ReadError, Store};
+
+/// bundles on each side of a bundle whose sizes it is compared with
+const SIZE_WINDOW: usize = 10;
+/// how many times smaller or larger than its neighbours' median size a bundle has to be to be reported
+const ODD_SIZE_FACTOR: usize = 10;
+
+/// Bundle files of a store compared with the ones expected for a block range.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InventoryReport {
+    /// bundle files expected for the block range
+    pub expected: usize,
+    /// expected bundle files found in the store, compressed or not
+    pub found: usize,
+    /// expected bundle files not in the store, compressed or not
+    pub missing: Vec<String>,
+    /// files that are not bundle files of the store's layout, such as bundles that do not start on a
+    /// bundle boundary
+    pub unexpected: Vec<String>,
+    /// bundle files with no content
+    pub empty: Vec<String>,
+    /// bundle files much smaller or larger than the bundles around them, with their size
+    pub odd_sized: Vec<(String, usize)>,
+    /// bundle files of the range compressed with zstd
+    pub compressed: usize,
+    /// bundle files of the range not compressed
+    pub uncompressed: usize,
+}
+
+impl InventoryReport {
+    /// whether the range holds both compressed and uncompressed bundle files
+    pub fn is_mixed(&self) -> bool {
+        self.compressed > 0 && self.uncompressed > 0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+            && self.unexpected.is_empty()
+            && self.empty.is_empty()
+            && self.odd_sized.is_empty()
+    }
+}
+
+/// lists the store and compares its files with the bundles expected for the blocks in
+/// `start_block..end_block`. Bundle files outside the range are ignored, and each bundle may be
+/// compressed or not
+pub async fn inventory(
+    store: &Store,
+    start_block: u64,
+    end_block: u64,
+) -> Result<InventoryReport, ReadError> {
+    let layout = store.layout();
+    let range = layout.bundle_start(start_block)..end_block;
+
+    let mut report = InventoryReport {
+        expected: layout.bundle_starts(start_block, end_block).count(),
+        ..Default::default()
+    };
+    // bundle files of the range by first block, as a bundle may be there both compressed and not
+    let mut bundles: BTreeMap<u64, Vec<(String, usize)>> = BTreeMap::new();
+
+    for (path, size) in store.list_files().await? {
+        match layout.parse_file_name(&path) {
+            Some((number, _)) if !range.contains(&number) => {}
+            Some((number, is_compressed)) => {
+                if is_compressed {
+                    report.compressed += 1;
+                } else {
+                    report.uncompressed += 1;
+                }
+                if size == 0 {
+                    report.empty.push(path.clone());
+                }
+                bundles.entry(number).or_default().push((path, size));
+            }
+            None => report.unexpected.push(path),
+        }
+    }
+
+    // missing bundles are named the way most bundles of the range are stored
+    let compressed = report.compressed >= report.uncompressed;
+    for bundle_start in layout.bundle_starts(start_block, end_block) {
+        if bundles.contains_key(&bundle_start) {
+            report.found += 1;
+        } else {
+            report
+                .missing
+                .push(layout.file_name(bundle_start, compressed));
+        }
+    }
+
+    for is_compressed in [true, false] {
+        let sized: Vec<(String, usize)> = bundles
+            .iter()
+            // the genesis bundle holds the genesis allocations, and dwarfs the bundles after it
+            .filter(|(number, _)| **number != 0)
+            .flat_map(|(_, files)| files)
+            .filter(|(path, size)| {
+                *size > 0 && layout.parse_file_name(path).map(|(_, c)| c) == Some(is_compressed)
+            })
+            .cloned()
+            .collect();
+        report.odd_sized.extend(odd_sized(&sized));
+    }
+
+    report.unexpected.sort();
+    report.empty.sort();
+    report.odd_sized.sort();
+    Ok(report)
+}
+
+/// bundles whose size is far from the median size of the bundles around them. Bundle sizes grow
+/// with the chain, so each bundle is only compared with its neighbours
+fn odd_sized(bundles: &[(String, usize)]) -> Vec<(String, usize)> {
+    let mut odd = Vec::new();
+    for (i, (path, size)) in bundles.iter().enumerate() {
+        let window = i.saturating_sub(SIZE_WINDOW)..(i + SIZE_WINDOW + 1).min(bundles.len());
+        let mut neighbours: Vec<usize> = window.filter(|&j| j != i).map(|j| bundles[j].1).collect();
+        // too few bundles to tell what size is normal
+        if neighbours.len() < 3 {
+            continue;
+        }
+
+        neighbours.sort_unstable();
+        let median = neighbours[neighbours.len() / 2];
+        if size * ODD_SIZE_FACTOR < median || *size > median * ODD_SIZE_FACTOR {
+            odd.push((path.clone(), *size));
+        }
+    }
+    odd
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::{path::Path, ObjectStore};
+
+    use super::*;
+    use crate::{layout::BundleLayout, memory::named_memory_store};
+
+    /// `memory://<name>` store holding files of the given paths and sizes
+    async fn store(name: &str, files: &[(String, usize)]) -> Store {
+        let memory = named_memory_store(name);
+        for (path, size) in files {
+            memory
+                .put(&Path::from(path.as_str()), vec![1; *size].into())
+                .await
+                .unwrap();
+        }
+        crate::store::new(format!("memory://{}", name), None).unwrap()
+    }
+
+    /// bundles starting at each of `starts`, of `size` bytes
+    fn bundles(
+        starts: impl IntoIterator<Item = u64>,
+        compressed: bool,
+        size: usize,
+    ) -> Vec<(String, usize)> {
+        let layout = BundleLayout::default();
+        starts
+            .into_iter()
+            .map(|start| (layout.file_name(start, compressed), size))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn complete_store() {
+        let files = bundles((0..1000).step_by(100), true, 1000);
+        let store = store("inventory-complete", &files).await;
+
+        let report = inventory(&store, 0, 1000).await.unwrap();
+        assert_eq!(report.expected, 10);
+        assert_eq!(report.found, 10);
+        assert_eq!(report.compressed, 10);
+        assert_eq!(report.uncompressed, 0);
+        assert!(report.is_complete());
+        assert!(!report.is_mixed());
+
+        // bundles outside the range are ignored, and the first bundle starts before the range
+        let report = inventory(&store, 150, 350).await.unwrap();
+        assert_eq!(report.expected, 3);
+        assert_eq!(report.found, 3);
+        assert_eq!(report.compressed, 3);
+        assert!(report.is_complete());
+    }
+
+    #[tokio::test]
+    async fn missing_and_unexpected_files() {
+        let mut files = bundles([0, 100, 200, 400], true, 1000);
+        files.push(("0000000150.dbin.zst".to_string(), 1000));
+        files.push(("notes.txt".to_string(), 10));
+        let store = store("inventory-missing", &files).await;
+
+        let report = inventory(&store, 0, 500).await.unwrap();
+        assert_eq!(report.expected, 5);
+        assert_eq!(report.found, 4);
+        assert_eq!(report.missing, vec!["0000000300.dbin.zst"]);
+        assert_eq!(report.unexpected, vec!["0000000150.dbin.zst", "notes.txt"]);
+        assert!(!report.is_complete());
+    }
+
+    #[tokio::test]
+    async fn empty_and_odd_sized_bundles() {
+        let mut files = bundles((100..2000).step_by(100), true, 1000);
+        files[6].1 = 0;
+        files[11].1 = 50;
+        files[15].1 = 20_000;
+        // the genesis bundle is much larger than the next ones, and is not reported
+        files.extend(bundles([0], true, 1_000_000));
+        let store = store("inventory-sizes", &files).await;
+
+        let report = inventory(&store, 0, 2000).await.unwrap();
+        assert_eq!(report.found, 20);
+        assert_eq!(report.empty, vec!["0000000700.dbin.zst"]);
+        assert_eq!(
+            report.odd_sized,
+            vec![
+                ("0000001200.dbin.zst".to_string(), 50),
+                ("0000001600.dbin.zst".to_string(), 20_000)
+            ]
+        );
+        assert!(!report.is_complete());
+    }
+
+    #[tokio::test]
+    async fn mixed_compression() {
+        let mut files = bundles([0, 100, 200], false, 1000);
+        files.extend(bundles([100, 400], true, 1000));
+        let store = store("inventory-mixed", &files).await;
+
+        let report = inventory(&store, 0, 500).await.unwrap();
+        assert_eq!(report.expected, 5);
+        // a bundle stored both ways is found once
+        assert_eq!(report.found, 4);
+        assert_eq!(report.compressed, 2);
+        assert_eq!(report.uncompressed, 3);
+        assert!(report.is_mixed());
+        // named the way most bundles are stored
+        assert_eq!(report.missing, vec!["0000000300.dbin"]);
+    }
+
+    #[tokio::test]
+    async fn files_under_the_base_path() {
+        let mut files: Vec<(String, usize)> = bundles([0, 100], true, 1000)
+            .into_iter()
+            .map(|(path, size)| (format!("mainnet/{}", path), size))
+            .collect();
+        files.push(("sepolia/0000000200.dbin.zst".to_string(), 1000));
+        store("inventory-base", &files).await;
+        let store = crate::store::new("memory://inventory-base/mainnet", None).unwrap();
+
+        let report = inventory(&store, 0, 300).await.unwrap();
+        assert_eq!(report.found, 2);
+        assert_eq!(report.missing, vec!["0000000200.dbin.zst"]);
+        assert!(report.unexpected.is_empty());
+    }
+}
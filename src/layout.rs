@@ -0,0 +1,405 @@
+use std::{fmt, iter::StepBy, ops::Range, str::FromStr};
+
+use thiserror::Error;
+
+/// default number of blocks in a bundle file
+pub const DEFAULT_BUNDLE_SIZE: u64 = 100;
+/// default number of blocks under a `{shard}` directory
+pub const DEFAULT_SHARD_SIZE: u64 = 1_000_000;
+/// default name of a bundle file, before the compression extension
+pub const DEFAULT_NAME_TEMPLATE: &str = "{block:010}.dbin";
+
+const COMPRESSED_EXTENSION: &str = ".zst";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    #[error("Invalid name template '{template}': {reason}")]
+    InvalidTemplate {
+        template: String,
+        reason: &'static str,
+    },
+}
+
+/// Value a placeholder of a [`NameTemplate`] stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// first block of the bundle
+    Block,
+    /// first block of the bundle divided by the shard size
+    Shard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// a number, zero-padded to the width
+    Number {
+        field: Field,
+        width: usize,
+    },
+}
+
+/// Path of a bundle file relative to the store, such as `{block:010}.dbin` or
+/// `{shard:04}/{block:010}.dbin`.
+///
+/// `{block}` is the first block of the bundle and `{shard}` that block divided by the shard
+/// size. Either can be zero-padded, as in `{block:010}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+impl FromStr for NameTemplate {
+    type Err = LayoutError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| LayoutError::InvalidTemplate {
+            template: template.to_string(),
+            reason,
+        };
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if rest[..open].contains('}') {
+                return Err(invalid("unopened placeholder"));
+            }
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed placeholder"))?
+                + open;
+
+            let placeholder = &rest[open + 1..close];
+            let (name, width) = match placeholder.split_once(':') {
+                Some((name, format)) => {
+                    let width = format
+                        .strip_prefix('0')
+                        .and_then(|width| width.parse().ok())
+                        .ok_or_else(|| invalid("padding must be written as `:0N`"))?;
+                    (name, width)
+                }
+                None => (placeholder, 0),
+            };
+            let field = match name {
+                "block" => Field::Block,
+                "shard" => Field::Shard,
+                _ => return Err(invalid("placeholders are `{block}` and `{shard}`")),
+            };
+            if matches!(parts.last(), Some(Part::Number { .. })) {
+                return Err(invalid("placeholders must be separated by text"));
+            }
+            parts.push(Part::Number { field, width });
+
+            rest = &rest[close + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("unopened placeholder"));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        if !parts.iter().any(|part| {
+            matches!(
+                part,
+                Part::Number {
+                    field: Field::Block,
+                    ..
+                }
+            )
+        }) {
+            return Err(invalid("a `{block}` placeholder is required"));
+        }
+        Ok(NameTemplate { parts })
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => write!(f, "{}", literal)?,
+                Part::Number { field, width } => {
+                    let name = match field {
+                        Field::Block => "block",
+                        Field::Shard => "shard",
+                    };
+                    match width {
+                        0 => write!(f, "{{{}}}", name)?,
+                        width => write!(f, "{{{}:0{}}}", name, width)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        DEFAULT_NAME_TEMPLATE
+            .parse()
+            .expect("default template is valid")
+    }
+}
+
+impl NameTemplate {
+    fn render(&self, block: u64, shard: u64) -> String {
+        let mut name = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => name.push_str(literal),
+                Part::Number { field, width } => {
+                    let value = match field {
+                        Field::Block => block,
+                        Field::Shard => shard,
+                    };
+                    name.push_str(&format!("{:0width$}", value, width = *width));
+                }
+            }
+        }
+        name
+    }
+
+    /// the `{block}` value of a name rendered from this template. The other placeholders are
+    /// skipped over
+    fn parse_block(&self, name: &str) -> Option<u64> {
+        let mut block = None;
+        let mut rest = name;
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+                Part::Number { field, .. } => {
+                    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+                    let value = rest[..digits].parse().ok()?;
+                    if *field == Field::Block {
+                        block = Some(value);
+                    }
+                    rest = &rest[digits..];
+                }
+            }
+        }
+        if rest.is_empty() {
+            block
+        } else {
+            None
+        }
+    }
+}
+
+/// How blocks are split into bundle files, and where those files are in the store.
+#[derive(Debug, Clone, Default)]
+pub struct BundleLayout {
+    /// blocks in a bundle file. Bundles start on multiples of it
+    pub bundle_size: Option<u64>,
+    /// path of a bundle file relative to the store, before the `.zst` extension of compressed files
+    pub name_template: Option<NameTemplate>,
+    /// blocks under each `{shard}` of the name template
+    pub shard_size: Option<u64>,
+}
+
+impl BundleLayout {
+    pub fn bundle_size(&self) -> u64 {
+        self.bundle_size.unwrap_or(DEFAULT_BUNDLE_SIZE).max(1)
+    }
+
+    pub fn name_template(&self) -> NameTemplate {
+        self.name_template.clone().unwrap_or_default()
+    }
+
+    pub fn shard_size(&self) -> u64 {
+        self.shard_size.unwrap_or(DEFAULT_SHARD_SIZE).max(1)
+    }
+
+    /// first block of the bundle holding `block`
+    pub fn bundle_start(&self, block: u64) -> u64 {
+        block / self.bundle_size() * self.bundle_size()
+    }
+
+    /// first blocks of the bundles holding the blocks in `start_block..end_block`
+    pub fn bundle_starts(&self, start_block: u64, end_block: u64) -> StepBy<Range<u64>> {
+        (self.bundle_start(start_block)..end_block).step_by(self.bundle_size() as usize)
+    }
+
+    /// path of the bundle file starting at `bundle_start`
+    pub fn file_name(&self, bundle_start: u64, compressed: bool) -> String {
+        let mut name = self
+            .name_template()
+            .render(bundle_start, bundle_start / self.shard_size());
+        if compressed {
+            name.push_str(COMPRESSED_EXTENSION);
+        }
+        name
+    }
+
+    /// paths of the bundle files holding the blocks in `start_block..end_block`
+    pub fn file_names(&self, start_block: u64, end_block: u64, compressed: bool) -> Vec<String> {
+        self.bundle_starts(start_block, end_block)
+            .map(|bundle_start| self.file_name(bundle_start, compressed))
+            .collect()
+    }
+
+    /// first block and compression of the bundle file at `path`, if it is a bundle file of this
+    /// layout, named exactly as [`BundleLayout::file_name`] would name it
+    pub fn parse_file_name(&self, path: &str) -> Option<(u64, bool)> {
+        let (name, compressed) = match path.strip_suffix(COMPRESSED_EXTENSION) {
+            Some(name) => (name, true),
+            None => (path, false),
+        };
+
+        let bundle_start = self.name_template().parse_block(name)?;
+        (bundle_start % self.bundle_size() == 0 && self.file_name(bundle_start, compressed) == path)
+            .then_some((bundle_start, compressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str) -> NameTemplate {
+        template.parse().unwrap()
+    }
+
+    fn invalid_reason(template: &str) -> &'static str {
+        match template.parse::<NameTemplate>() {
+            Err(LayoutError::InvalidTemplate { reason, .. }) => reason,
+            Ok(_) => panic!("{} parsed", template),
+        }
+    }
+
+    #[test]
+    fn templates_display_as_written() {
+        for written in [
+            DEFAULT_NAME_TEMPLATE,
+            "{block}.dbin",
+            "{shard:04}/{block:010}.dbin",
+            "mainnet/blocks-{block}-{shard}.bin",
+        ] {
+            assert_eq!(template(written).to_string(), written);
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert_eq!(
+            invalid_reason("{shard}.dbin"),
+            "a `{block}` placeholder is required"
+        );
+        assert_eq!(invalid_reason("{block.dbin"), "unclosed placeholder");
+        assert_eq!(invalid_reason("block}.dbin"), "unopened placeholder");
+        assert_eq!(invalid_reason("{block}}.dbin"), "unopened placeholder");
+        assert_eq!(
+            invalid_reason("{number}.dbin"),
+            "placeholders are `{block}` and `{shard}`"
+        );
+        assert_eq!(
+            invalid_reason("{block:10}.dbin"),
+            "padding must be written as `:0N`"
+        );
+        assert_eq!(
+            invalid_reason("{shard}{block}.dbin"),
+            "placeholders must be separated by text"
+        );
+    }
+
+    #[test]
+    fn render_pads_numbers() {
+        assert_eq!(
+            template(DEFAULT_NAME_TEMPLATE).render(100, 0),
+            "0000000100.dbin"
+        );
+        assert_eq!(template("{block}.dbin").render(100, 0), "100.dbin");
+        assert_eq!(
+            template("{shard:04}/{block:010}.dbin").render(12_345_600, 12),
+            "0012/0012345600.dbin"
+        );
+        // numbers wider than the padding are not cut
+        assert_eq!(template("{block:02}.dbin").render(12_345, 0), "12345.dbin");
+    }
+
+    #[test]
+    fn default_layout_file_names() {
+        let layout = BundleLayout::default();
+        assert_eq!(layout.file_name(0, true), "0000000000.dbin.zst");
+        assert_eq!(layout.file_name(100, false), "0000000100.dbin");
+        assert_eq!(layout.bundle_start(199), 100);
+        assert_eq!(
+            layout.file_names(150, 350, true),
+            vec![
+                "0000000100.dbin.zst",
+                "0000000200.dbin.zst",
+                "0000000300.dbin.zst"
+            ]
+        );
+    }
+
+    #[test]
+    fn sharded_layout_file_names() {
+        let layout = BundleLayout {
+            bundle_size: Some(1000),
+            name_template: Some(template("{shard:04}/{block:010}.dbin")),
+            shard_size: Some(100_000),
+        };
+        assert_eq!(layout.file_name(0, true), "0000/0000000000.dbin.zst");
+        assert_eq!(layout.file_name(1_234_000, false), "0012/0001234000.dbin");
+        assert_eq!(layout.bundle_start(1_234_567), 1_234_000);
+    }
+
+    #[test]
+    fn parse_file_name_inverts_file_name() {
+        let layouts = [
+            BundleLayout::default(),
+            BundleLayout {
+                bundle_size: Some(1000),
+                name_template: Some(template("{shard:04}/{block:010}.dbin")),
+                shard_size: Some(100_000),
+            },
+            BundleLayout {
+                name_template: Some(template("blocks-{block}.bin")),
+                ..Default::default()
+            },
+        ];
+        for layout in layouts.iter() {
+            for bundle_start in [0, 1000, 1_234_000] {
+                for compressed in [true, false] {
+                    let name = layout.file_name(bundle_start, compressed);
+                    assert_eq!(
+                        layout.parse_file_name(&name),
+                        Some((bundle_start, compressed)),
+                        "{}",
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_file_name_rejects_other_files() {
+        let layout = BundleLayout::default();
+        // not on a bundle boundary
+        assert_eq!(layout.parse_file_name("0000000150.dbin.zst"), None);
+        // not padded as the template pads it
+        assert_eq!(layout.parse_file_name("100.dbin.zst"), None);
+        assert_eq!(layout.parse_file_name("0000000100.dbin.gz"), None);
+        assert_eq!(layout.parse_file_name("0000000100.dbin.zst.partial"), None);
+        assert_eq!(layout.parse_file_name("notes.txt"), None);
+        assert_eq!(layout.parse_file_name(".dbin"), None);
+
+        let sharded = BundleLayout {
+            name_template: Some(template("{shard:04}/{block:010}.dbin")),
+            shard_size: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            sharded.parse_file_name("0001/0000001100.dbin.zst"),
+            Some((1100, true))
+        );
+        // under the wrong shard
+        assert_eq!(sharded.parse_file_name("0002/0000001100.dbin.zst"), None);
+    }
+}
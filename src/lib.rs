@@ -1,5 +1,20 @@
+pub mod azure;
+pub mod cache;
+pub mod checkpoint;
+pub mod diff;
+pub mod epoch_accumulator;
+pub mod era1;
 pub mod era_verifier;
+pub mod headers;
+pub mod inclusion_proof;
+pub mod inventory;
+pub mod layout;
+pub mod memory;
+pub mod metrics;
+pub mod post_merge;
+pub mod receipts;
 pub mod s3;
 pub mod store;
 pub mod stream;
+pub mod transactions;
 pub mod utils;
@@ -1,8 +1,24 @@
-use std::env;
+use std::{env, net::SocketAddr, path::Path, sync::Arc, time::Duration};
 
 use clap::{Parser, Subcommand};
+use dotenv::dotenv;
 
-use flat_head::era_verifier::verify_eras;
+use flat_head::{
+    azure::{AzureCredentials, AzureOptions},
+    cache::{BundleCache, CacheOptions},
+    diff::diff_stores,
+    era1::{compare_era1_with_store, export_era1, validate_era1, Era1},
+    era_verifier::{verify_eras, EpochReport, VerifyOptions, MAX_EPOCH_SIZE},
+    inclusion_proof::{prove_block, verify_proof_file},
+    inventory::{inventory, InventoryReport},
+    layout::{BundleLayout, NameTemplate},
+    metrics::serve_metrics,
+    post_merge::{verify_post_merge_blocks, BlockStatus, PostMergeValidator},
+    s3::{S3Credentials, S3Options},
+    store::{self, RetryOptions, StoreOptions},
+    stream::stream_validation_async,
+};
+use ssz::Encode;
 use trin_validation::accumulator::PreMergeAccumulator;
 
 #[derive(Parser)]
@@ -11,6 +27,155 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count, help = "Increase debug level (use -d for debug, -dd for trace, etc.)")]
     debug: u8,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Serve Prometheus metrics on this address, e.g. 0.0.0.0:9100"
+    )]
+    metrics_addr: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Attempts at reading a file from the store before giving up on transient errors"
+    )]
+    retry_attempts: Option<u32>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Delay before the first retry of a store read, in milliseconds. It doubles on every retry"
+    )]
+    retry_backoff_ms: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Upper bound of the delay between two store reads, in milliseconds"
+    )]
+    retry_max_backoff_ms: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Wait exactly the backoff between retries, without random jitter"
+    )]
+    no_retry_jitter: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Directory to cache the files read from stores in. Cached files are checked against the store's size and ETag before being used"
+    )]
+    cache_dir: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "cache_dir",
+        help = "Upper bound of the size of the cache, in bytes. The least recently used files are evicted first [default: 10 GiB]"
+    )]
+    cache_max_size: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Blocks in each bundle file. Bundles start on multiples of it [default: 100]"
+    )]
+    bundle_size: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Path of a bundle file in the store, before the .zst extension. {block} is the bundle's first block and {shard} that block divided by --shard-size; both can be zero-padded, e.g. {shard:04}/{block:010}.dbin [default: {block:010}.dbin]"
+    )]
+    bundle_name_template: Option<NameTemplate>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Blocks under each {shard} of the bundle name template [default: 1000000]"
+    )]
+    shard_size: Option<u64>,
+
+    #[arg(long, global = true, help = "Region of S3 buckets")]
+    s3_region: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Endpoint of an S3 compatible API for s3:// stores, e.g. http://localhost:9000"
+    )]
+    s3_endpoint: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Send S3 requests to https://<bucket>.<endpoint> instead of the path-style https://<endpoint>/<bucket>"
+    )]
+    s3_virtual_hosted_style: bool,
+
+    #[arg(
+        long,
+        global = true,
+        conflicts_with_all = ["s3_access_key_id", "s3_anonymous"],
+        help = "Take S3 credentials, region and endpoint from this profile of ~/.aws/credentials and ~/.aws/config"
+    )]
+    s3_profile: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "s3_secret_access_key",
+        conflicts_with = "s3_anonymous",
+        help = "Access key id of S3 stores. Credentials are taken from the AWS_* environment variables if none provided"
+    )]
+    s3_access_key_id: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "s3_access_key_id",
+        help = "Secret access key of S3 stores"
+    )]
+    s3_secret_access_key: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "s3_access_key_id",
+        help = "Session token of temporary S3 credentials"
+    )]
+    s3_session_token: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Do not sign S3 requests, for public buckets and servers without authentication"
+    )]
+    s3_anonymous: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Storage account of az:// and abfs:// stores. Taken from AZURE_STORAGE_ACCOUNT_NAME if none provided"
+    )]
+    azure_account: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Shared access signature of Azure stores. Credentials are taken from the AZURE_* environment variables if none provided"
+    )]
+    azure_sas_token: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Read Azure stores from a local Azurite emulator, at AZURITE_BLOB_STORAGE_URL or http://127.0.0.1:10000"
+    )]
+    azure_use_emulator: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -36,9 +201,215 @@ enum Commands {
         // epoch to end in. The interval is inclusive
         end_epoch: Option<usize>,
 
-        #[clap(short = 'c', long, default_value = "true")]
-        // Where to decompress files from zstd or not.
-        decompress: Option<bool>,
+        #[clap(short = 'p', long)]
+        // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
+        // then the store_url can point to seaweed-fs with S3 compatibility enabled and work as intended.
+        compatible: Option<String>,
+
+        #[clap(short = 'j', long)]
+        // how many epochs to validate at the same time.
+        jobs: Option<usize>,
+
+        #[clap(long)]
+        // how many bundle files to fetch from the store at the same time, across all epochs.
+        max_concurrent_reads: Option<usize>,
+
+        #[clap(long)]
+        // rebuild each block's transactions root from its transaction traces and check it against the header
+        check_transactions_root: bool,
+
+        #[clap(long)]
+        // rebuild each block's receipts root and logs bloom from its receipts and check them against the header
+        check_receipts_root: bool,
+
+        #[clap(long)]
+        // directory to write the SSZ epoch accumulator of every validated epoch to
+        export_accumulators: Option<String>,
+
+        #[clap(long)]
+        // JSON file the outcome of every epoch is saved to as soon as the epoch is checked
+        checkpoint_file: Option<String>,
+
+        #[clap(long, requires = "checkpoint_file")]
+        // skip the epochs the checkpoint file records as valid, and check the others again
+        resume: bool,
+    },
+    /// Validates length-prefixed bincode header records read from stdin.
+    StreamValidate {
+        #[clap(short, long)]
+        // master accumulator file. default Portal Network file will be used if none provided
+        master_acc_file: Option<String>,
+    },
+    /// Validates post-merge flat files against beacon chain historical roots and summaries.
+    VerifyPostMerge {
+        #[clap(short = 'b', long)]
+        // directory where flat files are located
+        store_url: String,
+
+        #[clap(long)]
+        // SSZ file with the `historical_roots` of a beacon state
+        historical_roots_file: String,
+
+        #[clap(long)]
+        // SSZ file with the `historical_summaries` of a beacon state
+        historical_summaries_file: String,
+
+        #[clap(long)]
+        // directory holding one SSZ block proof per block, named `{block_number:010}.ssz`
+        proofs_dir: String,
+
+        #[clap(short, long)]
+        // block to start from.
+        start_block: u64,
+
+        #[clap(short, long)]
+        // block to end in. The interval is exclusive
+        end_block: u64,
+
+        #[clap(short = 'p', long)]
+        // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
+        // then the store_url can point to seaweed-fs with S3 compatibility enabled and work as intended.
+        compatible: Option<String>,
+
+        #[clap(long)]
+        // how many bundle files to fetch from the store at the same time.
+        max_concurrent_reads: Option<usize>,
+    },
+    /// Builds a Portal Network `HeaderWithProof` for a pre-merge block, once its epoch validates.
+    Prove {
+        #[clap(short = 'b', long)]
+        // directory where flat files are located
+        store_url: String,
+
+        #[clap(short, long)]
+        // master accumulator file. default Portal Network file will be used if none provided
+        master_acc_file: Option<String>,
+
+        #[clap(long)]
+        // block to build the proof for
+        block: u64,
+
+        #[clap(short, long)]
+        // file to write the SSZ encoded proof to. It is printed as hex if none provided
+        output: Option<String>,
+
+        #[clap(short = 'p', long)]
+        // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
+        // then the store_url can point to seaweed-fs with S3 compatibility enabled and work as intended.
+        compatible: Option<String>,
+    },
+    /// Checks a header inclusion proof against the master accumulator, without reading any store.
+    VerifyProof {
+        #[clap(short, long)]
+        // master accumulator file. default Portal Network file will be used if none provided
+        master_acc_file: Option<String>,
+
+        #[clap(long)]
+        // SSZ file with either a `HeaderWithProof` or a bare epoch accumulator proof
+        proof_file: String,
+
+        #[clap(long)]
+        // file with the RLP encoded header. Not needed if the proof file is a `HeaderWithProof`
+        header_file: Option<String>,
+
+        #[clap(long, requires = "block")]
+        // flat file holding the block to check, instead of an RLP header. Requires `--block`
+        flat_file: Option<String>,
+
+        #[clap(long)]
+        // block of `--flat-file` to check
+        block: Option<u64>,
+    },
+    /// Converts a pre-merge epoch of flat files into an era1 file, once the epoch validates.
+    ExportEra1 {
+        #[clap(short = 'b', long)]
+        // directory where flat files are located
+        store_url: String,
+
+        #[clap(short, long)]
+        // master accumulator file. default Portal Network file will be used if none provided
+        master_acc_file: Option<String>,
+
+        #[clap(short, long)]
+        // epoch to convert
+        epoch: usize,
+
+        #[clap(short, long)]
+        // directory to write the era1 file to
+        output_dir: String,
+
+        #[clap(short = 'p', long)]
+        // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
+        // then the store_url can point to seaweed-fs with S3 compatibility enabled and work as intended.
+        compatible: Option<String>,
+    },
+    /// Validates an era1 file against the master accumulator, and optionally against flat files.
+    VerifyEra1 {
+        #[clap(long)]
+        // era1 file to validate
+        era1_file: String,
+
+        #[clap(short, long)]
+        // master accumulator file. default Portal Network file will be used if none provided
+        master_acc_file: Option<String>,
+
+        #[clap(short = 'b', long)]
+        // directory where flat files are located. If provided, every block of the era1 file is
+        // compared with the same block in the flat files
+        store_url: Option<String>,
+
+        #[clap(short = 'p', long)]
+        // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
+        // then the store_url can point to seaweed-fs with S3 compatibility enabled and work as intended.
+        compatible: Option<String>,
+    },
+    /// Compares the flat files of two stores block by block.
+    Diff {
+        #[clap(long)]
+        // directory where the first copy of the flat files is located
+        left_store_url: String,
+
+        #[clap(long)]
+        // directory where the second copy of the flat files is located
+        right_store_url: String,
+
+        #[clap(short, long)]
+        // block to start from.
+        start_block: u64,
+
+        #[clap(short, long)]
+        // block to end in. The interval is exclusive
+        end_block: u64,
+
+        #[clap(long)]
+        // API the left store_url is compatible with, as in `--compatible` of the other commands
+        left_compatible: Option<String>,
+
+        #[clap(long)]
+        // API the right store_url is compatible with, as in `--compatible` of the other commands
+        right_compatible: Option<String>,
+    },
+    /// Lists the bundle files of a store and reports the missing, unexpected and suspicious ones.
+    Inventory {
+        #[clap(short = 'b', long)]
+        // directory where flat files are located
+        store_url: String,
+
+        #[clap(long, default_value = "0", conflicts_with = "start_block")]
+        // epoch to start from.
+        start_epoch: usize,
+
+        #[clap(long, conflicts_with = "start_block")]
+        // epoch to end in. The interval is inclusive. Only the start epoch is listed if none provided
+        end_epoch: Option<usize>,
+
+        #[clap(long, requires = "end_block")]
+        // block to start from, instead of an epoch.
+        start_block: Option<u64>,
+
+        #[clap(long, requires = "start_block")]
+        // block to end in. The interval is exclusive
+        end_block: Option<u64>,
 
         #[clap(short = 'p', long)]
         // indicates if the store_url is compatible with some API. E.g., if `--compatible s3` is used,
@@ -50,6 +421,8 @@ enum Commands {
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    // S3 credentials may be kept in a .env file
+    dotenv().ok();
 
     match cli.debug {
         0 => env::set_var("RUST_LOG", "info"),
@@ -58,14 +431,82 @@ async fn main() {
     }
     env_logger::init();
 
+    let retry = RetryOptions {
+        max_attempts: cli.retry_attempts,
+        initial_backoff: cli.retry_backoff_ms.map(Duration::from_millis),
+        max_backoff: cli.retry_max_backoff_ms.map(Duration::from_millis),
+        jitter: Some(!cli.no_retry_jitter),
+    };
+
+    let cache = cli.cache_dir.as_ref().map(|cache_dir| {
+        Arc::new(
+            BundleCache::open(
+                cache_dir,
+                CacheOptions {
+                    max_size: cli.cache_max_size,
+                },
+            )
+            .expect("failed to open cache directory"),
+        )
+    });
+
+    let store_options = StoreOptions {
+        s3: S3Options {
+            region: cli.s3_region.clone(),
+            endpoint: cli.s3_endpoint.clone(),
+            virtual_hosted_style: Some(cli.s3_virtual_hosted_style),
+            credentials: match (
+                &cli.s3_profile,
+                &cli.s3_access_key_id,
+                &cli.s3_secret_access_key,
+            ) {
+                _ if cli.s3_anonymous => S3Credentials::Anonymous,
+                (Some(profile), _, _) => S3Credentials::Profile(profile.clone()),
+                (None, Some(access_key_id), Some(secret_access_key)) => S3Credentials::Static {
+                    access_key_id: access_key_id.clone(),
+                    secret_access_key: secret_access_key.clone(),
+                    session_token: cli.s3_session_token.clone(),
+                },
+                _ => S3Credentials::Environment,
+            },
+        },
+        azure: AzureOptions {
+            account: cli.azure_account.clone(),
+            use_emulator: Some(cli.azure_use_emulator),
+            credentials: match &cli.azure_sas_token {
+                Some(sas_token) => AzureCredentials::SasToken(sas_token.clone()),
+                None => AzureCredentials::Environment,
+            },
+        },
+    };
+
+    let layout = BundleLayout {
+        bundle_size: cli.bundle_size,
+        name_template: cli.bundle_name_template.clone(),
+        shard_size: cli.shard_size,
+    };
+
+    if let Some(metrics_addr) = cli.metrics_addr {
+        if let Err(e) = serve_metrics(metrics_addr).await {
+            log::error!("failed to serve metrics on {}: {:#}", metrics_addr, e);
+            std::process::exit(1);
+        }
+    }
+
     match &cli.command {
         Some(Commands::EraValidate {
-            decompress,
             store_url,
             master_acc_file,
             start_epoch,
             end_epoch,
             compatible,
+            jobs,
+            max_concurrent_reads,
+            check_transactions_root,
+            check_receipts_root,
+            export_accumulators,
+            checkpoint_file,
+            resume,
         }) => {
             println!(
                 "Starting era validation {} - {}",
@@ -73,32 +514,400 @@ async fn main() {
                 end_epoch.map(|x| x.to_string()).unwrap_or("".to_string())
             );
 
-            let macc = match master_acc_file {
-                Some(master_accumulator_file) => {
-                    PreMergeAccumulator::try_from_file(master_accumulator_file.into())
-                        .map_err(|_| panic!("failed to parse master accumulator file"))
-                }
-                None => Ok(PreMergeAccumulator::default()),
-            };
+            let macc = load_master_accumulator(master_acc_file.as_deref());
 
             match verify_eras(
                 store_url.to_string(),
-                macc.unwrap(),
+                macc,
                 compatible.clone(),
                 *start_epoch,
                 *end_epoch,
-                *decompress,
+                VerifyOptions {
+                    max_concurrent_epochs: *jobs,
+                    max_concurrent_reads: *max_concurrent_reads,
+                    check_transactions_root: *check_transactions_root,
+                    check_receipts_root: *check_receipts_root,
+                    export_accumulators: export_accumulators.as_ref().map(Into::into),
+                    checkpoint_file: checkpoint_file.as_ref().map(Into::into),
+                    resume: *resume,
+                    ignore_lock_file: false,
+                    retry: retry.clone(),
+                    cache: cache.clone(),
+                    layout: layout.clone(),
+                    store: store_options.clone(),
+                },
             )
             .await
             {
+                Ok(reports) => {
+                    for report in reports.iter() {
+                        print_report(report);
+                    }
+
+                    let validated: Vec<usize> = reports
+                        .iter()
+                        .filter(|report| report.is_valid())
+                        .map(|report| report.epoch)
+                        .collect();
+                    println!("Epochs validated: {:?}", validated);
+
+                    if validated.len() != reports.len() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::StreamValidate { master_acc_file }) => {
+            let macc = load_master_accumulator(master_acc_file.as_deref());
+
+            match stream_validation_async(macc, tokio::io::stdin(), tokio::io::stdout()).await {
                 Ok(result) => {
-                    println!("Epochs validated: {:?}", result);
+                    log::info!("Epochs validated: {:?}", result);
+                }
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                }
+            }
+        }
+        Some(Commands::VerifyPostMerge {
+            store_url,
+            historical_roots_file,
+            historical_summaries_file,
+            proofs_dir,
+            start_block,
+            end_block,
+            compatible,
+            max_concurrent_reads,
+        }) => {
+            let validator = PostMergeValidator::try_from_files(
+                historical_roots_file,
+                historical_summaries_file,
+            )
+            .expect("failed to parse historical roots and summaries files");
+
+            let results = match verify_post_merge_blocks(
+                store_url.to_string(),
+                compatible.clone(),
+                &validator,
+                proofs_dir.into(),
+                *start_block,
+                *end_block,
+                &VerifyOptions {
+                    max_concurrent_reads: *max_concurrent_reads,
+                    retry: retry.clone(),
+                    cache: cache.clone(),
+                    layout: layout.clone(),
+                    store: store_options.clone(),
+                    ..Default::default()
+                },
+            )
+            .await
+            {
+                Ok(results) => results,
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                    return;
+                }
+            };
+
+            let failed: Vec<u64> = results
+                .iter()
+                .filter(|(_, result)| result.is_err())
+                .map(|(block_number, _)| *block_number)
+                .collect();
+            // Prague headers commit to a requests hash firehose does not store
+            let unchecked: Vec<u64> = results
+                .iter()
+                .filter(|(_, result)| matches!(result, Ok(BlockStatus::HeaderUnchecked)))
+                .map(|(block_number, _)| *block_number)
+                .collect();
+            println!(
+                "Blocks validated: {}, header unchecked: {:?}, failed: {:?}",
+                results.len() - failed.len() - unchecked.len(),
+                unchecked,
+                failed
+            );
+        }
+        Some(Commands::Prove {
+            store_url,
+            master_acc_file,
+            block,
+            output,
+            compatible,
+        }) => {
+            let macc = load_master_accumulator(master_acc_file.as_deref());
+            let blocks_store =
+                store::new_with_options(store_url, compatible.clone(), &store_options)
+                    .expect("failed to create blocks store")
+                    .with_retry_options(retry.clone())
+                    .with_cache(cache.clone())
+                    .with_layout(layout.clone());
+
+            match prove_block(&blocks_store, &macc, *block).await {
+                Ok(header_with_proof) => {
+                    let bytes = header_with_proof.as_ssz_bytes();
+                    match output {
+                        Some(output) => {
+                            std::fs::write(output, bytes).expect("failed to write proof file");
+                            println!("Proof of block {} written to {}", block, output);
+                        }
+                        None => println!("{}", alloy_primitives::hex::encode_prefixed(bytes)),
+                    }
                 }
                 Err(e) => {
                     log::error!("error: {:#}", e);
                 }
             }
         }
+        Some(Commands::VerifyProof {
+            master_acc_file,
+            proof_file,
+            header_file,
+            flat_file,
+            block,
+        }) => {
+            let macc = load_master_accumulator(master_acc_file.as_deref());
+
+            // clap makes `--flat-file` require `--block`
+            match verify_proof_file(
+                &macc,
+                Path::new(proof_file),
+                header_file.as_deref().map(Path::new),
+                flat_file.as_deref().map(Path::new).zip(*block),
+            ) {
+                Ok(block_number) => println!("Proof of block {} is valid", block_number),
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::ExportEra1 {
+            store_url,
+            master_acc_file,
+            epoch,
+            output_dir,
+            compatible,
+        }) => {
+            let macc = load_master_accumulator(master_acc_file.as_deref());
+            let blocks_store =
+                store::new_with_options(store_url, compatible.clone(), &store_options)
+                    .expect("failed to create blocks store")
+                    .with_retry_options(retry.clone())
+                    .with_cache(cache.clone())
+                    .with_layout(layout.clone());
+
+            match export_era1(&blocks_store, &macc, *epoch, output_dir.as_ref()).await {
+                Ok(path) => println!("Epoch {} written to {}", epoch, path.display()),
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                }
+            }
+        }
+        Some(Commands::VerifyEra1 {
+            era1_file,
+            master_acc_file,
+            store_url,
+            compatible,
+        }) => {
+            let macc = load_master_accumulator(master_acc_file.as_deref());
+            let era1 = Era1::try_from_file(era1_file).expect("failed to parse era1 file");
+
+            match validate_era1(&era1, &macc) {
+                Ok(epoch) => println!("Era1 epoch {} is valid", epoch),
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                    return;
+                }
+            }
+
+            if let Some(store_url) = store_url {
+                let blocks_store =
+                    store::new_with_options(store_url, compatible.clone(), &store_options)
+                        .expect("failed to create blocks store")
+                        .with_retry_options(retry.clone())
+                        .with_cache(cache.clone())
+                        .with_layout(layout.clone());
+
+                match compare_era1_with_store(&era1, &blocks_store).await {
+                    Ok(comparison) => {
+                        println!(
+                            "Blocks compared with flat files: {}, mismatches: {:?}, missing from flat files: {:?}",
+                            comparison.compared, comparison.mismatches, comparison.missing_blocks
+                        );
+                        for (file_name, error) in comparison.read_errors.iter() {
+                            println!("  could not read {}: {}", file_name, error);
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("error: {:#}", e);
+                    }
+                }
+            }
+        }
+        Some(Commands::Diff {
+            left_store_url,
+            right_store_url,
+            start_block,
+            end_block,
+            left_compatible,
+            right_compatible,
+        }) => {
+            let left_store =
+                store::new_with_options(left_store_url, left_compatible.clone(), &store_options)
+                    .expect("failed to create left blocks store")
+                    .with_retry_options(retry.clone())
+                    .with_cache(cache.clone())
+                    .with_layout(layout.clone());
+            let right_store =
+                store::new_with_options(right_store_url, right_compatible.clone(), &store_options)
+                    .expect("failed to create right blocks store")
+                    .with_retry_options(retry.clone())
+                    .with_cache(cache.clone())
+                    .with_layout(layout.clone());
+
+            let report =
+                match diff_stores(&left_store, &right_store, *start_block, *end_block).await {
+                    Ok(report) => report,
+                    Err(e) => {
+                        log::error!("error: {:#}", e);
+                        std::process::exit(1);
+                    }
+                };
+
+            for (block_number, difference) in report.differences.iter() {
+                println!("Block {}: {}", block_number, difference);
+            }
+            for file_name in report.only_in_left.iter() {
+                println!("Bundle {} is only in the left store", file_name);
+            }
+            for file_name in report.only_in_right.iter() {
+                println!("Bundle {} is only in the right store", file_name);
+            }
+            for (file_name, error) in report.read_errors.iter() {
+                println!("Bundle {} could not be read: {}", file_name, error);
+            }
+            println!(
+                "Blocks compared: {}, differing: {}",
+                report.compared_blocks,
+                report.differences.len()
+            );
+        }
+        Some(Commands::Inventory {
+            store_url,
+            start_epoch,
+            end_epoch,
+            start_block,
+            end_block,
+            compatible,
+        }) => {
+            let (start_block, end_block) = match (start_block, end_block) {
+                (Some(start_block), Some(end_block)) => (*start_block, *end_block),
+                _ => (
+                    (*start_epoch * MAX_EPOCH_SIZE) as u64,
+                    ((end_epoch.unwrap_or(*start_epoch) + 1) * MAX_EPOCH_SIZE) as u64,
+                ),
+            };
+            let blocks_store =
+                store::new_with_options(store_url, compatible.clone(), &store_options)
+                    .expect("failed to create blocks store")
+                    .with_retry_options(retry.clone())
+                    .with_cache(cache.clone())
+                    .with_layout(layout.clone());
+
+            match inventory(&blocks_store, start_block, end_block).await {
+                Ok(report) => print_inventory(&report),
+                Err(e) => {
+                    log::error!("error: {:#}", e);
+                }
+            }
+        }
         None => {}
     }
 }
+
+fn print_inventory(report: &InventoryReport) {
+    for file_name in report.missing.iter() {
+        println!("Missing bundle {}", file_name);
+    }
+    for file_name in report.unexpected.iter() {
+        println!("Unexpected file {}", file_name);
+    }
+    for file_name in report.empty.iter() {
+        println!("Empty bundle {}", file_name);
+    }
+    for (file_name, size) in report.odd_sized.iter() {
+        println!("Bundle {} has an unusual size: {} bytes", file_name, size);
+    }
+    if report.is_mixed() {
+        println!(
+            "The range mixes {} compressed and {} uncompressed bundles",
+            report.compressed, report.uncompressed
+        );
+    }
+    println!(
+        "Bundles expected: {}, found: {}, missing: {}",
+        report.expected,
+        report.found,
+        report.missing.len()
+    );
+}
+
+fn print_report(report: &EpochReport) {
+    if report.resumed {
+        println!(
+            "Epoch {}: {} (from checkpoint)",
+            report.epoch, report.status
+        );
+        return;
+    }
+    println!("Epoch {}: {}", report.epoch, report.status);
+    for (kind, failures) in [
+        ("missing bundles", &report.missing_bundles),
+        ("decode failures", &report.decode_failures),
+        (
+            "header conversion failures",
+            &report.header_conversion_failures,
+        ),
+    ] {
+        if !failures.is_empty() {
+            println!("  {} {}: {:?}", failures.count, kind, failures.files);
+        }
+    }
+    if !report.header_hash_mismatches.is_empty() {
+        println!(
+            "  header hash mismatches in blocks: {:?}",
+            report.header_hash_mismatches
+        );
+    }
+    if !report.transactions_root_mismatches.is_empty() {
+        println!(
+            "  transactions root mismatches in blocks: {:?}",
+            report.transactions_root_mismatches
+        );
+    }
+    if !report.receipts_root_mismatches.is_empty() {
+        println!(
+            "  receipts root mismatches in blocks: {:?}",
+            report.receipts_root_mismatches
+        );
+    }
+    if let Some(path) = &report.exported_accumulator {
+        println!("  accumulator written to {}", path.display());
+    }
+}
+
+/// loads the master accumulator from a file, falling back to the default Portal Network one
+fn load_master_accumulator(master_acc_file: Option<&str>) -> PreMergeAccumulator {
+    match master_acc_file {
+        Some(master_accumulator_file) => {
+            PreMergeAccumulator::try_from_file(master_accumulator_file.into())
+                .unwrap_or_else(|_| panic!("failed to parse master accumulator file"))
+        }
+        None => PreMergeAccumulator::default(),
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use object_store::{memory::InMemory, path::Path, ObjectStore};
+use thiserror::Error;
+
+use crate::store::Store;
+
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("IO error reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Storage error: {0}")]
+    Storage(#[from] object_store::Error),
+}
+
+/// in-memory object store of `memory://<name>` store URLs. Every URL with the same name reads
+/// the same store, which lives as long as the process
+pub fn named_memory_store(name: &str) -> Arc<InMemory> {
+    static STORES: OnceLock<Mutex<HashMap<String, Arc<InMemory>>>> = OnceLock::new();
+
+    STORES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .clone()
+}
+
+/// copies every file under `dir`, such as `tests/compressed`, into `store` under `prefix`,
+/// keeping its path relative to `dir`. Returns how many files were copied
+pub async fn load_dir(
+    store: &dyn ObjectStore,
+    dir: impl Into<PathBuf>,
+    prefix: &Path,
+) -> Result<usize, LoadError> {
+    let mut loaded = 0;
+    let mut dirs = vec![(dir.into(), prefix.clone())];
+
+    while let Some((dir, prefix)) = dirs.pop() {
+        let io_error = |source| LoadError::Io {
+            path: dir.clone(),
+            source,
+        };
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(io_error)?;
+
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let path = entry.path();
+            let location = prefix.child(entry.file_name().to_string_lossy().as_ref());
+
+            if entry.file_type().await.map_err(io_error)?.is_dir() {
+                dirs.push((path, location));
+                continue;
+            }
+
+            let bytes = tokio::fs::read(&path)
+                .await
+                .map_err(|source| LoadError::Io { path, source })?;
+            store.put(&location, bytes.into()).await?;
+            loaded += 1;
+        }
+    }
+
+    Ok(loaded)
+}
+
+/// in-memory store holding a copy of every file under `dir`, for tests that should not depend on
+/// where the fixtures are on disk once loaded
+pub async fn store_from_dir(dir: impl Into<PathBuf>) -> Result<Store, LoadError> {
+    let dir = dir.into();
+    let memory = Arc::new(InMemory::new());
+    load_dir(memory.as_ref(), &dir, &Path::default()).await?;
+    // copies of the same directory hold the same files, so they may share cached files
+    Ok(Store::from_object_store(
+        memory,
+        format!("memory+file://{}", dir.display()),
+    ))
+}
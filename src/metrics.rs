@@ -0,0 +1,248 @@
+use std::{net::SocketAddr, sync::OnceLock};
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, Opts, Registry, TextEncoder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Prometheus metrics of store reads and epoch validation.
+pub struct Metrics {
+    registry: Registry,
+    /// bundle files fetched, per store backend
+    pub bundles_fetched: IntCounterVec,
+    /// bytes of bundle files downloaded, per store backend
+    pub bytes_downloaded: IntCounterVec,
+    /// reads retried after a transient error, per store backend
+    pub store_retries: IntCounterVec,
+    /// bundle files read from the local cache, per store backend
+    pub cache_hits: IntCounterVec,
+    /// bundle files missing from the local cache or stale, per store backend
+    pub cache_misses: IntCounterVec,
+    /// time taken to fetch a bundle file, per store backend
+    pub store_latency_seconds: HistogramVec,
+    /// time taken to decode a bundle file into blocks
+    pub decode_seconds: Histogram,
+    /// blocks that could not be converted into header records
+    pub header_conversion_errors: IntCounter,
+    /// epochs that matched the master accumulator
+    pub epochs_validated: IntCounter,
+    /// epochs that did not validate, whatever the reason
+    pub epochs_failed: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let bundles_fetched = IntCounterVec::new(
+            Opts::new(
+                "flat_head_bundles_fetched_total",
+                "Bundle files fetched from the store",
+            ),
+            &["backend"],
+        )?;
+        let bytes_downloaded = IntCounterVec::new(
+            Opts::new(
+                "flat_head_bytes_downloaded_total",
+                "Bytes of bundle files downloaded from the store",
+            ),
+            &["backend"],
+        )?;
+        let store_retries = IntCounterVec::new(
+            Opts::new(
+                "flat_head_store_retries_total",
+                "Store reads retried after a transient error",
+            ),
+            &["backend"],
+        )?;
+        let cache_hits = IntCounterVec::new(
+            Opts::new(
+                "flat_head_cache_hits_total",
+                "Bundle files read from the local cache",
+            ),
+            &["backend"],
+        )?;
+        let cache_misses = IntCounterVec::new(
+            Opts::new(
+                "flat_head_cache_misses_total",
+                "Bundle files missing from the local cache or stale",
+            ),
+            &["backend"],
+        )?;
+        let store_latency_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "flat_head_store_latency_seconds",
+                "Time taken to fetch a bundle file from the store",
+            )
+            .buckets(exponential_buckets(0.01, 2.0, 14)?),
+            &["backend"],
+        )?;
+        let decode_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "flat_head_decode_seconds",
+                "Time taken to decode a bundle file into blocks",
+            )
+            .buckets(exponential_buckets(0.001, 2.0, 14)?),
+        )?;
+        let header_conversion_errors = IntCounter::new(
+            "flat_head_header_conversion_errors_total",
+            "Blocks that could not be converted into header records",
+        )?;
+        let epochs_validated = IntCounter::new(
+            "flat_head_epochs_validated_total",
+            "Epochs that matched the master accumulator",
+        )?;
+        let epochs_failed = IntCounter::new(
+            "flat_head_epochs_failed_total",
+            "Epochs that did not validate",
+        )?;
+
+        registry.register(Box::new(bundles_fetched.clone()))?;
+        registry.register(Box::new(bytes_downloaded.clone()))?;
+        registry.register(Box::new(store_retries.clone()))?;
+        registry.register(Box::new(cache_hits.clone()))?;
+        registry.register(Box::new(cache_misses.clone()))?;
+        registry.register(Box::new(store_latency_seconds.clone()))?;
+        registry.register(Box::new(decode_seconds.clone()))?;
+        registry.register(Box::new(header_conversion_errors.clone()))?;
+        registry.register(Box::new(epochs_validated.clone()))?;
+        registry.register(Box::new(epochs_failed.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            bundles_fetched,
+            bytes_downloaded,
+            store_retries,
+            cache_hits,
+            cache_misses,
+            store_latency_seconds,
+            decode_seconds,
+            header_conversion_errors,
+            epochs_validated,
+            epochs_failed,
+        })
+    }
+
+    /// all metrics in the Prometheus text format
+    pub fn encode(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// metrics of this process. They are always collected, and only served with [`serve_metrics`]
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions are valid"))
+}
+
+/// serves the metrics over HTTP on `addr`, answering every request with the text format.
+///
+/// Binding the address is the only error returned: connections are then served in the
+/// background for as long as the runtime lives. Returns the address bound, which tells the
+/// port picked when `addr` has port 0
+pub async fn serve_metrics(addr: SocketAddr) -> Result<SocketAddr, std::io::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    log::info!("Serving metrics on {}", local_addr);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(respond(socket));
+                }
+                Err(e) => log::error!("Error accepting metrics connection: {}", e),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn respond(mut socket: TcpStream) {
+    // the request itself does not matter, only that it was sent
+    let mut request = [0u8; 1024];
+    if let Err(e) = socket.read(&mut request).await {
+        log::debug!("Error reading metrics request: {}", e);
+        return;
+    }
+
+    let body = metrics().encode().unwrap_or_else(|e| {
+        log::error!("Error encoding metrics: {}", e);
+        Vec::new()
+    });
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        TextEncoder::new().format_type(),
+        body.len()
+    );
+
+    if let Err(e) = socket.write_all(head.as_bytes()).await {
+        log::debug!("Error writing metrics response: {}", e);
+        return;
+    }
+    if let Err(e) = socket.write_all(&body).await {
+        log::debug!("Error writing metrics response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_encoded() {
+        let before = metrics().header_conversion_errors.get();
+        metrics().header_conversion_errors.inc_by(2);
+        assert!(metrics().header_conversion_errors.get() >= before + 2);
+
+        metrics()
+            .bundles_fetched
+            .with_label_values(&["metrics-test-encode"])
+            .inc_by(3);
+        let encoded = String::from_utf8(metrics().encode().unwrap()).unwrap();
+        assert!(encoded
+            .lines()
+            .any(|line| line
+                == r#"flat_head_bundles_fetched_total{backend="metrics-test-encode"} 3"#));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_over_http() {
+        metrics()
+            .cache_hits
+            .with_label_values(&["metrics-test-http"])
+            .inc();
+        let addr = serve_metrics("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body
+            .lines()
+            .any(|line| line == r#"flat_head_cache_hits_total{backend="metrics-test-http"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn bind_error_is_returned() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let e = serve_metrics(addr).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::AddrInUse);
+    }
+}
@@ -0,0 +1,667 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use futures::stream::{self, StreamExt};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use sf_protos::ethereum::r#type::v2::Block;
+
+use crate::{
+    era_verifier::{VerifyOptions, MERGE_BLOCK},
+    headers::{block_hash, check_header_hash, HeaderError},
+    store,
+};
+
+/// number of slots covered by a single historical root or historical summary
+pub const SLOTS_PER_HISTORICAL_ROOT: u64 = 8192;
+/// first slot of the Capella fork, from which `historical_summaries` replace `historical_roots`
+pub const CAPELLA_SLOT: u64 = 6_209_536;
+/// first slot of the Deneb fork, from which execution payloads hold 17 fields
+pub const DENEB_SLOT: u64 = 8_626_176;
+
+/// depth of the proof of a beacon block root inside a `HistoricalBatch`
+const HISTORICAL_ROOTS_PROOF_DEPTH: usize = 14;
+/// depth of the proof of a beacon block root inside a `block_roots` vector
+const HISTORICAL_SUMMARIES_PROOF_DEPTH: usize = 13;
+/// generalized index of `body.execution_payload.block_hash` in a Bellatrix or Capella `BeaconBlock`
+const EXECUTION_BLOCK_HASH_GINDEX: u64 = 3228;
+/// generalized index of `body.execution_payload.block_hash` in a Deneb `BeaconBlock`
+const EXECUTION_BLOCK_HASH_GINDEX_DENEB: u64 = 6444;
+
+type Root = [u8; 32];
+
+#[derive(Error, Debug)]
+pub enum PostMergeError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid SSZ encoding: {0}")]
+    InvalidSsz(String),
+    #[error("Block {0} is before the merge")]
+    PreMergeBlock(u64),
+    #[error("Slot {slot} does not belong to the fork covered by its proof")]
+    ForkMismatch { slot: u64 },
+    #[error("No trusted root for historical index {0}")]
+    MissingHistoricalRoot(usize),
+    #[error("Execution block hash is not part of beacon block {0:#x?}")]
+    ExecutionProofMismatch(Root),
+    #[error("Beacon block at slot {0} is not part of the trusted historical roots")]
+    BeaconProofMismatch(u64),
+    #[error("Bundle could not be read: {0}")]
+    BundleUnavailable(String),
+    #[error("Header error: {0}")]
+    Header(#[from] HeaderError),
+}
+
+/// `historical_roots` of a beacon state, frozen at Capella.
+///
+/// Each root is the hash tree root of a `HistoricalBatch` of 8192 block and state roots.
+#[derive(Debug, Clone, Default)]
+pub struct HistoricalRoots(pub Vec<Root>);
+
+impl HistoricalRoots {
+    /// decodes an SSZ `List[Root, HISTORICAL_ROOTS_LIMIT]`
+    pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, PostMergeError> {
+        Ok(HistoricalRoots(split_roots(bytes)?))
+    }
+
+    pub fn try_from_file(path: impl AsRef<Path>) -> Result<Self, PostMergeError> {
+        Self::from_ssz_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoricalSummary {
+    pub block_summary_root: Root,
+    pub state_summary_root: Root,
+}
+
+/// `historical_summaries` of a beacon state, appended to from Capella onwards.
+#[derive(Debug, Clone, Default)]
+pub struct HistoricalSummaries(pub Vec<HistoricalSummary>);
+
+impl HistoricalSummaries {
+    /// decodes an SSZ `List[HistoricalSummary, HISTORICAL_ROOTS_LIMIT]`
+    pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, PostMergeError> {
+        let roots = split_roots(bytes)?;
+        if roots.len() % 2 != 0 {
+            return Err(PostMergeError::InvalidSsz(
+                "historical summaries are 64 bytes each".to_string(),
+            ));
+        }
+
+        Ok(HistoricalSummaries(
+            roots
+                .chunks_exact(2)
+                .map(|pair| HistoricalSummary {
+                    block_summary_root: pair[0],
+                    state_summary_root: pair[1],
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn try_from_file(path: impl AsRef<Path>) -> Result<Self, PostMergeError> {
+        Self::from_ssz_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Proof that an execution block belongs to a beacon block, and that this beacon block is part
+/// of the trusted beacon history. Follows the Portal Network `BlockProofHistoricalRoots` and
+/// `BlockProofHistoricalSummaries` containers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockProof {
+    pub beacon_block_proof: Vec<Root>,
+    pub beacon_block_root: Root,
+    pub execution_block_proof: Vec<Root>,
+    pub slot: u64,
+}
+
+impl BlockProof {
+    /// decodes either a `BlockProofHistoricalRoots` or a `BlockProofHistoricalSummaries`.
+    ///
+    /// The former is a fixed 840 bytes, a size the latter can never have.
+    pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, PostMergeError> {
+        const ROOTS_PROOF_SIZE: usize = (HISTORICAL_ROOTS_PROOF_DEPTH + 1 + 11) * 32 + 8;
+        const SUMMARIES_FIXED_SIZE: usize = (HISTORICAL_SUMMARIES_PROOF_DEPTH + 1) * 32 + 4 + 8;
+
+        if bytes.len() == ROOTS_PROOF_SIZE {
+            let roots = split_roots(&bytes[..ROOTS_PROOF_SIZE - 8])?;
+            let (beacon_block_proof, rest) = roots.split_at(HISTORICAL_ROOTS_PROOF_DEPTH);
+            return Ok(BlockProof {
+                beacon_block_proof: beacon_block_proof.to_vec(),
+                beacon_block_root: rest[0],
+                execution_block_proof: rest[1..].to_vec(),
+                slot: read_u64(&bytes[ROOTS_PROOF_SIZE - 8..]),
+            });
+        }
+
+        if bytes.len() < SUMMARIES_FIXED_SIZE {
+            return Err(PostMergeError::InvalidSsz(format!(
+                "block proof of {} bytes is too short",
+                bytes.len()
+            )));
+        }
+
+        let offset_start = (HISTORICAL_SUMMARIES_PROOF_DEPTH + 1) * 32;
+        let offset = u32::from_le_bytes(
+            bytes[offset_start..offset_start + 4]
+                .try_into()
+                .expect("slice is 4 bytes"),
+        ) as usize;
+        if offset != SUMMARIES_FIXED_SIZE {
+            return Err(PostMergeError::InvalidSsz(format!(
+                "unexpected execution block proof offset {}",
+                offset
+            )));
+        }
+
+        let roots = split_roots(&bytes[..offset_start])?;
+        let (beacon_block_proof, rest) = roots.split_at(HISTORICAL_SUMMARIES_PROOF_DEPTH);
+        Ok(BlockProof {
+            beacon_block_proof: beacon_block_proof.to_vec(),
+            beacon_block_root: rest[0],
+            execution_block_proof: split_roots(&bytes[offset..])?,
+            slot: read_u64(&bytes[offset_start + 4..offset]),
+        })
+    }
+
+    pub fn try_from_file(path: impl AsRef<Path>) -> Result<Self, PostMergeError> {
+        Self::from_ssz_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Outcome of a post-merge block whose proof was checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// the header hashes to the stored block hash, which the proof vouches for
+    Valid,
+    /// the proof vouches for the stored block hash, but the header is from Prague or later and
+    /// its fields could not be checked against that hash
+    HeaderUnchecked,
+}
+
+/// Validates post-merge execution blocks against trusted beacon chain history.
+#[derive(Debug, Clone, Default)]
+pub struct PostMergeValidator {
+    pub historical_roots: HistoricalRoots,
+    pub historical_summaries: HistoricalSummaries,
+}
+
+impl PostMergeValidator {
+    pub fn try_from_files(
+        historical_roots_file: impl AsRef<Path>,
+        historical_summaries_file: impl AsRef<Path>,
+    ) -> Result<Self, PostMergeError> {
+        Ok(PostMergeValidator {
+            historical_roots: HistoricalRoots::try_from_file(historical_roots_file)?,
+            historical_summaries: HistoricalSummaries::try_from_file(historical_summaries_file)?,
+        })
+    }
+
+    /// checks that `block_hash` is the execution payload of the beacon block in `proof`, and that
+    /// this beacon block is part of the trusted `historical_roots` or `historical_summaries`
+    pub fn validate_block_hash(
+        &self,
+        block_hash: Root,
+        proof: &BlockProof,
+    ) -> Result<(), PostMergeError> {
+        let slot = proof.slot;
+
+        let (execution_depth, execution_gindex) = match proof.execution_block_proof.len() {
+            11 if slot < DENEB_SLOT => (11, EXECUTION_BLOCK_HASH_GINDEX),
+            12 if slot >= DENEB_SLOT => (12, EXECUTION_BLOCK_HASH_GINDEX_DENEB),
+            _ => return Err(PostMergeError::ForkMismatch { slot }),
+        };
+        if !is_valid_merkle_branch(
+            block_hash,
+            &proof.execution_block_proof,
+            execution_depth,
+            execution_gindex - (1 << execution_depth),
+            proof.beacon_block_root,
+        ) {
+            return Err(PostMergeError::ExecutionProofMismatch(
+                proof.beacon_block_root,
+            ));
+        }
+
+        let (historical_root, depth) = match proof.beacon_block_proof.len() {
+            HISTORICAL_ROOTS_PROOF_DEPTH if slot < CAPELLA_SLOT => {
+                let index = (slot / SLOTS_PER_HISTORICAL_ROOT) as usize;
+                let root = self
+                    .historical_roots
+                    .0
+                    .get(index)
+                    .ok_or(PostMergeError::MissingHistoricalRoot(index))?;
+                (*root, HISTORICAL_ROOTS_PROOF_DEPTH)
+            }
+            HISTORICAL_SUMMARIES_PROOF_DEPTH if slot >= CAPELLA_SLOT => {
+                let index = ((slot - CAPELLA_SLOT) / SLOTS_PER_HISTORICAL_ROOT) as usize;
+                let summary = self
+                    .historical_summaries
+                    .0
+                    .get(index)
+                    .ok_or(PostMergeError::MissingHistoricalRoot(index))?;
+                (summary.block_summary_root, HISTORICAL_SUMMARIES_PROOF_DEPTH)
+            }
+            _ => return Err(PostMergeError::ForkMismatch { slot }),
+        };
+
+        // block roots sit in the left-most subtree of both a `HistoricalBatch` and a `block_roots` vector
+        if !is_valid_merkle_branch(
+            proof.beacon_block_root,
+            &proof.beacon_block_proof,
+            depth,
+            slot % SLOTS_PER_HISTORICAL_ROOT,
+            historical_root,
+        ) {
+            return Err(PostMergeError::BeaconProofMismatch(slot));
+        }
+
+        Ok(())
+    }
+}
+
+/// validates the post-merge blocks in `start_block..end_block` read from the store at `store_url`.
+///
+/// The proof of each block is read from `proofs_dir`, as an SSZ file named after the
+/// block number (`{:010}.ssz`). Returns the outcome of every block in the range, where blocks
+/// from Prague on are [`BlockStatus::HeaderUnchecked`] rather than invalid.
+pub async fn verify_post_merge_blocks(
+    store_url: String,
+    compatible: Option<String>,
+    validator: &PostMergeValidator,
+    proofs_dir: PathBuf,
+    start_block: u64,
+    end_block: u64,
+    options: &VerifyOptions,
+) -> Result<Vec<(u64, Result<BlockStatus, PostMergeError>)>, anyhow::Error> {
+    let store = store::new_with_options(store_url, compatible, &options.store)
+        .context("failed to create blocks store")?
+        .with_retry_options(options.retry.clone())
+        .with_cache(options.cache.clone())
+        .with_layout(options.layout.clone());
+    let store = &store;
+    let layout = store.layout();
+
+    let mut bundles = stream::iter(layout.bundle_starts(start_block, end_block))
+        .map(|block_number| async move { (block_number, store.read_bundle(block_number).await.1) })
+        .buffered(options.max_concurrent_reads());
+
+    let mut results = Vec::new();
+
+    while let Some((bundle_start, bundle)) = bundles.next().await {
+        let blocks = match bundle {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                let message = e.to_string();
+                for number in bundle_start.max(start_block)
+                    ..(bundle_start + layout.bundle_size()).min(end_block)
+                {
+                    results.push((
+                        number,
+                        Err(PostMergeError::BundleUnavailable(message.clone())),
+                    ));
+                }
+                continue;
+            }
+        };
+
+        for block in blocks
+            .iter()
+            .filter(|block| (start_block..end_block).contains(&block.number))
+        {
+            let result = validate_block(validator, &proofs_dir, block);
+            if let Err(e) = &result {
+                log::error!("Block {} failed post-merge validation: {}", block.number, e);
+            }
+            results.push((block.number, result));
+        }
+    }
+
+    Ok(results)
+}
+
+fn validate_block(
+    validator: &PostMergeValidator,
+    proofs_dir: &Path,
+    block: &Block,
+) -> Result<BlockStatus, PostMergeError> {
+    if block.number < MERGE_BLOCK as u64 {
+        return Err(PostMergeError::PreMergeBlock(block.number));
+    }
+
+    // the proofs vouch for the stored hash, so make sure the header fields back it up
+    let status = match check_header_hash(block) {
+        Ok(()) => BlockStatus::Valid,
+        Err(HeaderError::UnsupportedFork(_)) => BlockStatus::HeaderUnchecked,
+        Err(e) => return Err(e.into()),
+    };
+
+    let proof = BlockProof::try_from_file(proofs_dir.join(format!("{:010}.ssz", block.number)))?;
+
+    validator.validate_block_hash(block_hash(block)?.0, &proof)?;
+    Ok(status)
+}
+
+/// `is_valid_merkle_branch` from the consensus specs
+fn is_valid_merkle_branch(
+    leaf: Root,
+    branch: &[Root],
+    depth: usize,
+    index: u64,
+    root: Root,
+) -> bool {
+    if branch.len() != depth {
+        return false;
+    }
+
+    let mut value = leaf;
+    for (i, sibling) in branch.iter().enumerate() {
+        let mut hasher = Sha256::new();
+        if (index >> i) & 1 == 1 {
+            hasher.update(sibling);
+            hasher.update(value);
+        } else {
+            hasher.update(value);
+            hasher.update(sibling);
+        }
+        value = hasher.finalize().into();
+    }
+
+    value == root
+}
+
+fn split_roots(bytes: &[u8]) -> Result<Vec<Root>, PostMergeError> {
+    if bytes.len() % 32 != 0 {
+        return Err(PostMergeError::InvalidSsz(format!(
+            "{} bytes is not a whole number of roots",
+            bytes.len()
+        )));
+    }
+
+    Ok(bytes
+        .chunks_exact(32)
+        .map(|chunk| chunk.try_into().expect("chunk is 32 bytes"))
+        .collect())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().expect("slice is 8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::headers::PRAGUE_BLOCK;
+
+    const BLOCK_HASH: Root = [0xaa; 32];
+
+    fn hash_pair(left: &Root, right: &Root) -> Root {
+        Sha256::new()
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+            .into()
+    }
+
+    /// root reached from `leaf` by hashing it with each sibling of `branch` in turn
+    fn root_from_branch(leaf: Root, branch: &[Root], index: u64) -> Root {
+        branch
+            .iter()
+            .enumerate()
+            .fold(leaf, |value, (level, sibling)| {
+                if (index >> level) & 1 == 1 {
+                    hash_pair(sibling, &value)
+                } else {
+                    hash_pair(&value, sibling)
+                }
+            })
+    }
+
+    /// root of `leaves` padded with zero roots to a power of two, and the branch of the leaf at
+    /// `index`
+    fn merkleize(mut leaves: Vec<Root>, mut index: usize) -> (Root, Vec<Root>) {
+        leaves.resize(leaves.len().next_power_of_two(), [0; 32]);
+        let mut branch = Vec::new();
+        while leaves.len() > 1 {
+            branch.push(leaves[index ^ 1]);
+            leaves = leaves
+                .chunks_exact(2)
+                .map(|pair| hash_pair(&pair[0], &pair[1]))
+                .collect();
+            index /= 2;
+        }
+        (leaves[0], branch)
+    }
+
+    fn branch(seed: u8, depth: usize) -> Vec<Root> {
+        (0..depth).map(|i| [seed + i as u8; 32]).collect()
+    }
+
+    /// a proof of `BLOCK_HASH` at `slot`, with a validator trusting the historical root it leads to
+    fn proof_at(slot: u64) -> (BlockProof, PostMergeValidator) {
+        let (execution_depth, execution_gindex) = if slot < DENEB_SLOT {
+            (11, EXECUTION_BLOCK_HASH_GINDEX)
+        } else {
+            (12, EXECUTION_BLOCK_HASH_GINDEX_DENEB)
+        };
+        let execution_block_proof = branch(1, execution_depth);
+        let beacon_block_root = root_from_branch(
+            BLOCK_HASH,
+            &execution_block_proof,
+            execution_gindex - (1 << execution_depth),
+        );
+
+        let mut validator = PostMergeValidator::default();
+        let beacon_block_proof = if slot < CAPELLA_SLOT {
+            let proof = branch(100, HISTORICAL_ROOTS_PROOF_DEPTH);
+            let root =
+                root_from_branch(beacon_block_root, &proof, slot % SLOTS_PER_HISTORICAL_ROOT);
+            validator.historical_roots.0 =
+                vec![[0; 32]; (slot / SLOTS_PER_HISTORICAL_ROOT) as usize];
+            validator.historical_roots.0.push(root);
+            proof
+        } else {
+            let proof = branch(100, HISTORICAL_SUMMARIES_PROOF_DEPTH);
+            let root =
+                root_from_branch(beacon_block_root, &proof, slot % SLOTS_PER_HISTORICAL_ROOT);
+            let summary = |block_summary_root| HistoricalSummary {
+                block_summary_root,
+                state_summary_root: [0; 32],
+            };
+            validator.historical_summaries.0 = vec![
+                summary([0; 32]);
+                ((slot - CAPELLA_SLOT) / SLOTS_PER_HISTORICAL_ROOT)
+                    as usize
+            ];
+            validator.historical_summaries.0.push(summary(root));
+            proof
+        };
+
+        let proof = BlockProof {
+            beacon_block_proof,
+            beacon_block_root,
+            execution_block_proof,
+            slot,
+        };
+        (proof, validator)
+    }
+
+    #[test]
+    fn valid_proofs_of_every_fork() {
+        for slot in [4_700_013, CAPELLA_SLOT + 8_200, DENEB_SLOT + 5] {
+            let (proof, validator) = proof_at(slot);
+            validator.validate_block_hash(BLOCK_HASH, &proof).unwrap();
+        }
+    }
+
+    #[test]
+    fn tampered_execution_branch_is_rejected() {
+        let (mut proof, validator) = proof_at(4_700_013);
+        proof.execution_block_proof[3][0] ^= 1;
+        assert!(matches!(
+            validator.validate_block_hash(BLOCK_HASH, &proof),
+            Err(PostMergeError::ExecutionProofMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn other_block_hash_is_rejected() {
+        let (proof, validator) = proof_at(CAPELLA_SLOT + 8_200);
+        assert!(matches!(
+            validator.validate_block_hash([0xbb; 32], &proof),
+            Err(PostMergeError::ExecutionProofMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn beacon_block_at_another_slot_is_rejected() {
+        let (mut proof, validator) = proof_at(4_700_013);
+        // same historical root, but another index inside it
+        proof.slot += 1;
+        assert!(matches!(
+            validator.validate_block_hash(BLOCK_HASH, &proof),
+            Err(PostMergeError::BeaconProofMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn proof_of_the_wrong_fork_is_rejected() {
+        // a Deneb execution proof is one level deeper than a Capella one
+        let (mut proof, validator) = proof_at(DENEB_SLOT + 5);
+        proof.slot = DENEB_SLOT - 5;
+        assert!(matches!(
+            validator.validate_block_hash(BLOCK_HASH, &proof),
+            Err(PostMergeError::ForkMismatch { .. })
+        ));
+
+        // historical roots proofs stop at Capella
+        let (mut proof, validator) = proof_at(4_700_013);
+        proof.slot = CAPELLA_SLOT + 13;
+        assert!(matches!(
+            validator.validate_block_hash(BLOCK_HASH, &proof),
+            Err(PostMergeError::ForkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_historical_root_is_reported() {
+        let (proof, mut validator) = proof_at(4_700_013);
+        validator.historical_roots.0.pop();
+        assert!(matches!(
+            validator.validate_block_hash(BLOCK_HASH, &proof),
+            Err(PostMergeError::MissingHistoricalRoot(_))
+        ));
+    }
+
+    /// SSZ encoding of `proof`, as a `BlockProofHistoricalRoots` before Capella and a
+    /// `BlockProofHistoricalSummaries` after
+    fn proof_to_ssz(proof: &BlockProof) -> Vec<u8> {
+        let mut bytes: Vec<u8> = proof.beacon_block_proof.concat();
+        bytes.extend_from_slice(&proof.beacon_block_root);
+        if proof.slot < CAPELLA_SLOT {
+            bytes.extend(proof.execution_block_proof.concat());
+            bytes.extend_from_slice(&proof.slot.to_le_bytes());
+        } else {
+            bytes.extend_from_slice(&((bytes.len() + 4 + 8) as u32).to_le_bytes());
+            bytes.extend_from_slice(&proof.slot.to_le_bytes());
+            bytes.extend(proof.execution_block_proof.concat());
+        }
+        bytes
+    }
+
+    #[test]
+    fn block_proofs_decode_from_ssz() {
+        let (roots_proof, _) = proof_at(4_700_013);
+        let bytes = proof_to_ssz(&roots_proof);
+        assert_eq!(BlockProof::from_ssz_bytes(&bytes).unwrap(), roots_proof);
+
+        let (summaries_proof, _) = proof_at(DENEB_SLOT + 5);
+        let bytes = proof_to_ssz(&summaries_proof);
+        assert_eq!(BlockProof::from_ssz_bytes(&bytes).unwrap(), summaries_proof);
+
+        assert!(BlockProof::from_ssz_bytes(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn execution_block_hash_gindices_follow_the_ssz_layout() {
+        // the body is the last of 5 `BeaconBlock` fields and the execution payload the tenth of
+        // 11 Capella body fields. The block hash is the 13th of 15 Capella payload fields, and
+        // Deneb's two blob gas fields take the payload to 17 fields and one level deeper
+        for (payload_fields, depth, gindex) in [
+            (15, 11, EXECUTION_BLOCK_HASH_GINDEX),
+            (17, 12, EXECUTION_BLOCK_HASH_GINDEX_DENEB),
+        ] {
+            let mut payload: Vec<Root> = (1..=payload_fields).map(|i| [i; 32]).collect();
+            payload[12] = BLOCK_HASH;
+            let (payload_root, payload_branch) = merkleize(payload, 12);
+
+            let mut body: Vec<Root> = (0x40..0x4b).map(|i| [i; 32]).collect();
+            body[9] = payload_root;
+            let (body_root, body_branch) = merkleize(body, 9);
+
+            let mut beacon_block: Vec<Root> = (0x80..0x85).map(|i| [i; 32]).collect();
+            beacon_block[4] = body_root;
+            let (beacon_block_root, beacon_block_branch) = merkleize(beacon_block, 4);
+
+            let branch = [payload_branch, body_branch, beacon_block_branch].concat();
+            assert_eq!(branch.len(), depth);
+            assert!(is_valid_merkle_branch(
+                BLOCK_HASH,
+                &branch,
+                depth,
+                gindex - (1 << depth),
+                beacon_block_root,
+            ));
+        }
+    }
+
+    #[test]
+    fn prague_blocks_are_reported_unchecked() {
+        let proofs_dir =
+            std::env::temp_dir().join(format!("flat-head-post-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&proofs_dir).unwrap();
+
+        let (proof, validator) = proof_at(DENEB_SLOT + 5);
+        let block = |number| Block {
+            number,
+            hash: BLOCK_HASH.to_vec(),
+            header: Some(sf_protos::ethereum::r#type::v2::BlockHeader {
+                number,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        for number in [PRAGUE_BLOCK - 1, PRAGUE_BLOCK] {
+            std::fs::write(
+                proofs_dir.join(format!("{:010}.ssz", number)),
+                proof_to_ssz(&proof),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            validate_block(&validator, &proofs_dir, &block(PRAGUE_BLOCK)).unwrap(),
+            BlockStatus::HeaderUnchecked
+        );
+        // before Prague, the header has to hash to the proven block hash
+        assert!(matches!(
+            validate_block(&validator, &proofs_dir, &block(PRAGUE_BLOCK - 1)),
+            Err(PostMergeError::Header(HeaderError::HashMismatch { .. }))
+        ));
+
+        // an unchecked header still needs a valid proof
+        let mut tampered = block(PRAGUE_BLOCK);
+        tampered.hash = vec![0xbb; 32];
+        assert!(matches!(
+            validate_block(&validator, &proofs_dir, &tampered),
+            Err(PostMergeError::ExecutionProofMismatch(_))
+        ));
+
+        std::fs::remove_dir_all(&proofs_dir).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use alloy_primitives::B256;
+use alloy_rlp::{BufMut, Encodable, Header};
+use thiserror::Error;
+
+use sf_protos::ethereum::r#type::v2::{
+    transaction_trace::Type, Block, Log, TransactionTrace, TransactionTraceStatus,
+};
+
+use crate::{transactions::ordered_trie_root, utils::read_hash};
+
+/// first block whose receipts carry a status code instead of an intermediate state root
+pub const BYZANTIUM_BLOCK: u64 = 4_370_000;
+
+/// size in bytes of a logs bloom filter
+const BLOOM_SIZE: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum ReceiptError {
+    #[error("Block {0} has no header")]
+    MissingHeader(u64),
+    #[error("Transaction {0} has no receipt")]
+    MissingReceipt(B256),
+    #[error("Transaction {hash} has unsupported type {tx_type}")]
+    UnsupportedType { hash: B256, tx_type: i32 },
+    #[error(
+        "Block {block}: receipts root mismatch, header has {expected} but receipts give {computed}"
+    )]
+    RootMismatch {
+        block: u64,
+        expected: B256,
+        computed: B256,
+    },
+    #[error("Block {0}: logs bloom does not match the receipts' blooms")]
+    BloomMismatch(u64),
+    #[error("{field} is {len} bytes long instead of 32")]
+    InvalidHash { field: &'static str, len: usize },
+}
+
+fn transaction_hash(trace: &TransactionTrace) -> Result<B256, ReceiptError> {
+    read_hash(&trace.hash).map_err(|len| ReceiptError::InvalidHash {
+        field: "Transaction hash",
+        len,
+    })
+}
+
+/// rebuilds the receipts trie root of a block from the receipts of its transaction traces
+pub fn receipts_root(block: &Block) -> Result<B256, ReceiptError> {
+    let encoded = block
+        .transaction_traces
+        .iter()
+        .map(|trace| encode_receipt(trace, block.number))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ordered_trie_root(&encoded))
+}
+
+/// ORs the blooms of every receipt in the block together
+pub fn logs_bloom(block: &Block) -> Result<[u8; BLOOM_SIZE], ReceiptError> {
+    let mut bloom = [0u8; BLOOM_SIZE];
+
+    for trace in block.transaction_traces.iter() {
+        let receipt = trace
+            .receipt
+            .as_ref()
+            .ok_or(ReceiptError::MissingReceipt(transaction_hash(trace)?))?;
+        for (byte, receipt_byte) in bloom.iter_mut().zip(receipt.logs_bloom.iter()) {
+            *byte |= receipt_byte;
+        }
+    }
+
+    Ok(bloom)
+}
+
+/// compares the receipts root and logs bloom rebuilt from the block's receipts with the ones in its header
+pub fn check_receipts_root(block: &Block) -> Result<(), ReceiptError> {
+    let header = block
+        .header
+        .as_ref()
+        .ok_or(ReceiptError::MissingHeader(block.number))?;
+
+    let computed = receipts_root(block)?;
+    let expected = read_hash(&header.receipt_root).map_err(|len| ReceiptError::InvalidHash {
+        field: "Receipts root",
+        len,
+    })?;
+    if computed != expected {
+        return Err(ReceiptError::RootMismatch {
+            block: block.number,
+            expected,
+            computed,
+        });
+    }
+
+    if logs_bloom(block)?.as_slice() != header.logs_bloom.as_slice() {
+        return Err(ReceiptError::BloomMismatch(block.number));
+    }
+
+    Ok(())
+}
+
+/// encodes a receipt the way it is stored in the receipts trie.
+///
+/// Receipts before Byzantium hold the intermediate state root, later ones the status code.
+/// Receipts of typed transactions are prefixed with the transaction type.
+pub fn encode_receipt(
+    trace: &TransactionTrace,
+    block_number: u64,
+) -> Result<Vec<u8>, ReceiptError> {
+    let hash = transaction_hash(trace)?;
+    let receipt = trace
+        .receipt
+        .as_ref()
+        .ok_or(ReceiptError::MissingReceipt(hash))?;
+    let tx_type = Type::try_from(trace.r#type).map_err(|_| ReceiptError::UnsupportedType {
+        hash,
+        tx_type: trace.r#type,
+    })?;
+
+    let mut payload = Vec::new();
+
+    if block_number < BYZANTIUM_BLOCK {
+        receipt.state_root.as_slice().encode(&mut payload);
+    } else {
+        let succeeded = trace.status == TransactionTraceStatus::Succeeded as i32;
+        (succeeded as u8).encode(&mut payload);
+    }
+    receipt.cumulative_gas_used.encode(&mut payload);
+    receipt.logs_bloom.as_slice().encode(&mut payload);
+    encode_logs(&receipt.logs, &mut payload);
+
+    let mut out = Vec::with_capacity(payload.len() + 5);
+    match tx_type {
+        Type::TrxTypeLegacy => {}
+        Type::TrxTypeAccessList | Type::TrxTypeDynamicFee | Type::TrxTypeBlob => {
+            out.push(trace.r#type as u8);
+        }
+        _ => {
+            return Err(ReceiptError::UnsupportedType {
+                hash,
+                tx_type: trace.r#type,
+            })
+        }
+    }
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&payload);
+
+    Ok(out)
+}
+
+fn encode_logs(logs: &[Log], out: &mut dyn BufMut) {
+    let mut payload = Vec::new();
+    for log in logs {
+        let mut log_payload = Vec::new();
+        log.address.as_slice().encode(&mut log_payload);
+        alloy_rlp::encode_list::<&[u8], [u8]>(
+            log.topics
+                .iter()
+                .map(|topic| topic.as_slice())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            &mut log_payload,
+        );
+        log.data.as_slice().encode(&mut log_payload);
+
+        Header {
+            list: true,
+            payload_length: log_payload.len(),
+        }
+        .encode(&mut payload);
+        payload.extend_from_slice(&log_payload);
+    }
+
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(out);
+    out.put_slice(&payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{b256, hex};
+    use alloy_rlp::Decodable;
+    use alloy_trie::EMPTY_ROOT_HASH;
+    use sf_protos::ethereum::r#type::v2::{BlockHeader, TransactionReceipt};
+
+    use super::*;
+    use crate::store::decode_bundle;
+
+    // roots of the receipts below, computed by a separate receipt encoder and trie implementation
+    const POST_BYZANTIUM_RECEIPTS_ROOT: B256 =
+        b256!("cd6f8a186fbc90a2c146e2438ca26c71e15db18afd24fa38ef15ac1522c325bb");
+    const PRE_BYZANTIUM_RECEIPTS_ROOT: B256 =
+        b256!("460b137550a1d4a5296c4240598da8e333473d2defa171ffa7f2955da75a9424");
+    /// bloom of the log of [`transfer_log`]
+    const TRANSFER_BLOOM: [u8; BLOOM_SIZE] = hex!("00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000040000000000000000000000000000000000000000000000008000000000000000000000000000000000004000000000000000002000000000000000000000000000000000000002010000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000100080000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000");
+
+    /// ERC-20 `Transfer` of 1,000,000 USDT units from `0x9d8a…4a4f` to `0x3535…3535`
+    fn transfer_log() -> Log {
+        Log {
+            address: hex!("dac17f958d2ee523a2206206994597c13d831ec7").to_vec(),
+            topics: vec![
+                hex!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef").to_vec(),
+                B256::left_padding_from(&hex!("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f")).to_vec(),
+                B256::left_padding_from(&[0x35; 20]).to_vec(),
+            ],
+            data: B256::left_padding_from(&1_000_000u32.to_be_bytes()).to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn receipt_trace(
+        tx_type: Type,
+        status: TransactionTraceStatus,
+        cumulative_gas_used: u64,
+        logs: Vec<Log>,
+    ) -> TransactionTrace {
+        let logs_bloom = if logs.is_empty() {
+            vec![0; BLOOM_SIZE]
+        } else {
+            TRANSFER_BLOOM.to_vec()
+        };
+        TransactionTrace {
+            r#type: tx_type as i32,
+            hash: vec![0x11; 32],
+            status: status as i32,
+            receipt: Some(TransactionReceipt {
+                state_root: vec![0x5e; 32],
+                cumulative_gas_used,
+                logs_bloom,
+                logs,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn block(number: u64, receipt_root: B256, transaction_traces: Vec<TransactionTrace>) -> Block {
+        Block {
+            number,
+            header: Some(BlockHeader {
+                number,
+                receipt_root: receipt_root.to_vec(),
+                logs_bloom: TRANSFER_BLOOM.to_vec(),
+                ..Default::default()
+            }),
+            transaction_traces,
+            ..Default::default()
+        }
+    }
+
+    fn trace(tx_type: Type, bloom_byte: u8) -> TransactionTrace {
+        TransactionTrace {
+            r#type: tx_type as i32,
+            hash: vec![0x11; 32],
+            status: TransactionTraceStatus::Succeeded as i32,
+            receipt: Some(TransactionReceipt {
+                state_root: vec![0x22; 32],
+                cumulative_gas_used: 21_000,
+                logs_bloom: vec![bloom_byte; BLOOM_SIZE],
+                logs: vec![Log {
+                    address: vec![0x33; 20],
+                    topics: vec![vec![0x44; 32], vec![0x55; 32]],
+                    data: vec![1, 2, 3],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// splits an encoded receipt into its first field and cumulative gas used
+    fn decode_prefix(mut encoded: &[u8]) -> (Vec<u8>, u64) {
+        let header = Header::decode(&mut encoded).unwrap();
+        assert!(header.list);
+        assert_eq!(header.payload_length, encoded.len());
+        let first = Header::decode_bytes(&mut encoded, false).unwrap().to_vec();
+        let gas = u64::decode(&mut encoded).unwrap();
+        (first, gas)
+    }
+
+    #[test]
+    fn pre_byzantium_receipt_holds_state_root() {
+        let encoded = encode_receipt(&trace(Type::TrxTypeLegacy, 0), BYZANTIUM_BLOCK - 1).unwrap();
+        assert_eq!(decode_prefix(&encoded), (vec![0x22; 32], 21_000));
+    }
+
+    #[test]
+    fn post_byzantium_receipt_holds_status() {
+        let encoded = encode_receipt(&trace(Type::TrxTypeLegacy, 0), BYZANTIUM_BLOCK).unwrap();
+        assert_eq!(decode_prefix(&encoded), (vec![1], 21_000));
+
+        let mut failed = trace(Type::TrxTypeLegacy, 0);
+        failed.status = TransactionTraceStatus::Reverted as i32;
+        let encoded = encode_receipt(&failed, BYZANTIUM_BLOCK).unwrap();
+        assert_eq!(decode_prefix(&encoded), (vec![], 21_000));
+    }
+
+    #[test]
+    fn typed_receipt_is_prefixed_with_its_type() {
+        let encoded = encode_receipt(&trace(Type::TrxTypeDynamicFee, 0), BYZANTIUM_BLOCK).unwrap();
+        assert_eq!(encoded[0], Type::TrxTypeDynamicFee as u8);
+        assert_eq!(decode_prefix(&encoded[1..]), (vec![1], 21_000));
+    }
+
+    #[test]
+    fn missing_receipt_is_an_error() {
+        let mut trace = trace(Type::TrxTypeLegacy, 0);
+        trace.receipt = None;
+        assert!(matches!(
+            encode_receipt(&trace, 0),
+            Err(ReceiptError::MissingReceipt(hash)) if hash == B256::repeat_byte(0x11)
+        ));
+    }
+
+    #[test]
+    fn logs_bloom_ors_receipt_blooms() {
+        let block = Block {
+            transaction_traces: vec![
+                trace(Type::TrxTypeLegacy, 0x01),
+                trace(Type::TrxTypeLegacy, 0x10),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(logs_bloom(&block).unwrap(), [0x11; BLOOM_SIZE]);
+    }
+
+    #[test]
+    fn post_byzantium_receipts_of_every_transaction_type() {
+        use TransactionTraceStatus::{Reverted, Succeeded};
+
+        let block = block(
+            19_426_587,
+            POST_BYZANTIUM_RECEIPTS_ROOT,
+            vec![
+                receipt_trace(Type::TrxTypeLegacy, Succeeded, 21_000, vec![]),
+                receipt_trace(
+                    Type::TrxTypeDynamicFee,
+                    Succeeded,
+                    72_000,
+                    vec![transfer_log()],
+                ),
+                receipt_trace(Type::TrxTypeAccessList, Reverted, 100_000, vec![]),
+                receipt_trace(Type::TrxTypeBlob, Succeeded, 121_000, vec![]),
+            ],
+        );
+        assert_eq!(receipts_root(&block).unwrap(), POST_BYZANTIUM_RECEIPTS_ROOT);
+        check_receipts_root(&block).unwrap();
+    }
+
+    #[test]
+    fn pre_byzantium_receipts_with_logs() {
+        use TransactionTraceStatus::Succeeded;
+
+        let mut block = block(
+            BYZANTIUM_BLOCK - 1,
+            PRE_BYZANTIUM_RECEIPTS_ROOT,
+            vec![
+                receipt_trace(Type::TrxTypeLegacy, Succeeded, 21_000, vec![]),
+                receipt_trace(Type::TrxTypeLegacy, Succeeded, 72_000, vec![transfer_log()]),
+            ],
+        );
+        assert_eq!(receipts_root(&block).unwrap(), PRE_BYZANTIUM_RECEIPTS_ROOT);
+        check_receipts_root(&block).unwrap();
+
+        // the header bloom has to cover the bloom of every receipt
+        block.header.as_mut().unwrap().logs_bloom = vec![0; BLOOM_SIZE];
+        assert!(matches!(
+            check_receipts_root(&block),
+            Err(ReceiptError::BloomMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn fixture_receipts_roots() {
+        let bytes = std::fs::read("tests/ethereum_firehose_first_8200/0000000000.dbin").unwrap();
+        let blocks = decode_bundle(&bytes).unwrap();
+        assert_eq!(blocks.len(), 100);
+
+        for block in blocks.iter() {
+            check_receipts_root(block).unwrap();
+            assert_eq!(receipts_root(block).unwrap(), EMPTY_ROOT_HASH);
+        }
+    }
+}
@@ -1,98 +1,322 @@
-use dotenv::dotenv;
-use header_accumulator::{
-    epoch::MAX_EPOCH_SIZE, era_validator::EraValidator, types::ExtHeaderRecord,
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
 };
-use std::env;
-use trin_validation::accumulator::PreMergeAccumulator;
 
-use decoder::handle_buf;
+use object_store::aws::AmazonS3Builder;
+use thiserror::Error;
 
-use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore};
+use crate::store::client_retry_config;
 
-use crate::utils::gen_dbin_filenames;
+#[derive(Error, Debug)]
+pub enum S3Error {
+    #[error("IO error reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("AWS profile '{0}' not found in the shared config or credentials files")]
+    MissingProfile(String),
+    #[error("AWS profile '{0}' has no access key id and secret access key")]
+    MissingCredentials(String),
+}
+
+/// Where the credentials used to sign S3 requests come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum S3Credentials {
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and the other `AWS_*` variables, falling back
+    /// to the instance metadata service or web identity when no keys are set.
+    #[default]
+    Environment,
+    /// A profile of the shared `~/.aws/credentials` and `~/.aws/config` files.
+    Profile(String),
+    /// Keys given directly.
+    Static {
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    },
+    /// Requests are not signed, for public buckets and servers without authentication.
+    Anonymous,
+}
+
+/// Configuration of every S3 and S3 compatible store.
+#[derive(Debug, Clone, Default)]
+pub struct S3Options {
+    /// region of the bucket. Taken from the environment or the profile if none provided
+    pub region: Option<String>,
+    /// endpoint of an S3 compatible API, such as `http://localhost:9000` for minio
+    pub endpoint: Option<String>,
+    /// send requests to `https://<bucket>.<endpoint>` instead of `https://<endpoint>/<bucket>`
+    pub virtual_hosted_style: Option<bool>,
+    pub credentials: S3Credentials,
+}
+
+impl S3Options {
+    pub fn virtual_hosted_style(&self) -> bool {
+        self.virtual_hosted_style.unwrap_or(false)
+    }
 
-fn handle_var(var_name: &str) -> String {
-    match env::var(var_name) {
-        Ok(value) => value,
-        Err(e) => {
-            println!("Error reading environment variable {}: {}", var_name, e);
-            std::process::exit(1);
+    /// builder of an S3 store for `bucket`, with these options applied. Options set here take
+    /// precedence over the ones found in the environment or the profile
+    pub fn builder(&self, bucket: &str) -> Result<AmazonS3Builder, S3Error> {
+        let mut builder = match &self.credentials {
+            S3Credentials::Environment => AmazonS3Builder::from_env(),
+            S3Credentials::Profile(name) => profile_builder(name)?,
+            S3Credentials::Static {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            } => {
+                let builder = AmazonS3Builder::new()
+                    .with_access_key_id(access_key_id)
+                    .with_secret_access_key(secret_access_key);
+                match session_token {
+                    Some(session_token) => builder.with_token(session_token),
+                    None => builder,
+                }
+            }
+            S3Credentials::Anonymous => AmazonS3Builder::new().with_skip_signature(true),
+        };
+
+        builder = builder
+            .with_bucket_name(bucket)
+            .with_virtual_hosted_style_request(self.virtual_hosted_style())
+            .with_retry(client_retry_config());
+        if let Some(region) = &self.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = &self.endpoint {
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_endpoint(endpoint);
         }
+        Ok(builder)
     }
 }
 
-pub async fn s3_fetch(
-    start_epoch: u64,
-    end_epoch: u64,
-    endpoint: Option<String>,
-    decompress: Option<bool>,
-) {
-    dotenv().ok();
-
-    let aws_region = handle_var("AWS_REGION");
-    let bucket_name = handle_var("BUCKET_NAME");
-    let access_key_id = handle_var("ACCESS_KEY_ID");
-    let secret_key = handle_var("SECRET_KEY");
+/// builder with the keys, region and endpoint of a profile of the shared AWS files
+fn profile_builder(name: &str) -> Result<AmazonS3Builder, S3Error> {
+    let values = profile_values(
+        &aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials"),
+        &aws_file("AWS_CONFIG_FILE", "config"),
+        name,
+    )?;
+    let (Some(access_key_id), Some(secret_access_key)) = (
+        values.get("aws_access_key_id"),
+        values.get("aws_secret_access_key"),
+    ) else {
+        return Err(S3Error::MissingCredentials(name.to_string()));
+    };
 
     let mut builder = AmazonS3Builder::new()
-        .with_region(aws_region)
-        .with_bucket_name(bucket_name)
         .with_access_key_id(access_key_id)
-        .with_secret_access_key(secret_key)
-        .with_allow_http(true);
+        .with_secret_access_key(secret_access_key);
+    if let Some(session_token) = values.get("aws_session_token") {
+        builder = builder.with_token(session_token);
+    }
+    if let Some(region) = values.get("region") {
+        builder = builder.with_region(region);
+    }
+    if let Some(endpoint) = values.get("endpoint_url") {
+        builder = builder
+            .with_allow_http(endpoint.starts_with("http://"))
+            .with_endpoint(endpoint);
+    }
+    Ok(builder)
+}
 
-    if let Some(endpoint) = endpoint {
-        builder = builder.with_endpoint(endpoint);
+/// keys and values of a profile, merged from the shared credentials and config files
+fn profile_values(
+    credentials_path: &Path,
+    config_path: &Path,
+    name: &str,
+) -> Result<HashMap<String, String>, S3Error> {
+    let credentials = read_profile(credentials_path, name)?;
+    // profiles other than the default one are prefixed in the config file
+    let config_section = match name {
+        "default" => name.to_string(),
+        _ => format!("profile {}", name),
+    };
+    let config = read_profile(config_path, &config_section)?;
+
+    if credentials.is_none() && config.is_none() {
+        return Err(S3Error::MissingProfile(name.to_string()));
     }
+    // keys may be in either file, the credentials file wins
+    let mut values = config.unwrap_or_default();
+    values.extend(credentials.unwrap_or_default());
+    Ok(values)
+}
 
-    let s3 = builder.build().unwrap();
-
-    let file_names = gen_dbin_filenames(start_epoch, end_epoch, decompress);
-
-    let mut headers: Vec<ExtHeaderRecord> = Vec::new();
-    for file_name in file_names {
-        let path_string = format!("/{}", file_name);
-        let path = Path::from(path_string);
-        let result = s3.get(&path).await.unwrap();
-
-        let bytes = result.bytes().await.unwrap();
-
-        // Use `as_ref` to get a &[u8] from `bytes` and pass it to `handle_buf`
-        match handle_buf(bytes.as_ref(), Some(false)) {
-            Ok(blocks) => {
-                let (successful_headers, _): (Vec<_>, Vec<_>) = blocks
-                    .iter()
-                    .cloned()
-                    .map(|block| ExtHeaderRecord::try_from(&block))
-                    .fold((Vec::new(), Vec::new()), |(mut succ, mut errs), res| {
-                        match res {
-                            Ok(header) => succ.push(header),
-                            Err(e) => {
-                                // Log the error or handle it as needed
-                                eprintln!("Error converting block: {:?}", e);
-                                errs.push(e);
-                            }
-                        };
-                        (succ, errs)
-                    });
-
-                headers.extend(successful_headers);
-                // Handle the successfully decoded blocks
-            }
-            Err(e) => {
-                log::error!("error: {:?}", e);
-                // Handle the decoding error
+/// path of a shared AWS file, from its environment variable or in `~/.aws`
+fn aws_file(var_name: &str, file_name: &str) -> PathBuf {
+    match env::var_os(var_name) {
+        Some(path) => path.into(),
+        None => env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(".aws")
+            .join(file_name),
+    }
+}
+
+/// keys and values of a section of an INI file. A missing file has no sections
+fn read_profile(path: &Path, section: &str) -> Result<Option<HashMap<String, String>>, S3Error> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(S3Error::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+        }
+    };
+
+    let mut values = None;
+    let mut in_section = false;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+            if in_section {
+                values.get_or_insert_with(HashMap::new);
             }
+            continue;
         }
-        if headers.len() >= 8192 {
-            let epoch_headers: Vec<ExtHeaderRecord> = headers.drain(0..MAX_EPOCH_SIZE).collect();
-            let valid_blocks = PreMergeAccumulator::default().era_validate(
-                epoch_headers,
-                start_epoch as usize,
-                Some(end_epoch as usize),
-                true,
-            );
-            println!("{:?} valid epochs", valid_blocks);
+        if let (true, Some((key, value))) = (in_section, line.split_once('=')) {
+            values
+                .get_or_insert_with(HashMap::new)
+                .insert(key.trim().to_string(), value.trim().to_string());
         }
     }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREDENTIALS: &str = "\
+# keys of every profile
+[default]
+aws_access_key_id = DEFAULTKEY
+aws_secret_access_key = defaultsecret
+
+; temporary keys
+[ staging ]
+aws_access_key_id=STAGINGKEY
+aws_secret_access_key=stagingsecret
+aws_session_token = stagingtoken
+
+[no-secret]
+aws_access_key_id = NOSECRETKEY
+";
+
+    const CONFIG: &str = "\
+[default]
+region = us-east-1
+
+[profile staging]
+region = eu-west-1
+endpoint_url = http://localhost:9000
+aws_access_key_id = IGNOREDKEY
+
+[profile config-only]
+aws_access_key_id = CONFIGKEY
+aws_secret_access_key = configsecret
+";
+
+    /// shared credentials and config files, written to a directory of their own
+    fn aws_files(name: &str) -> (PathBuf, PathBuf) {
+        let dir = env::temp_dir().join(format!("flat-head-s3-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let credentials_path = dir.join("credentials");
+        let config_path = dir.join("config");
+        std::fs::write(&credentials_path, CREDENTIALS).unwrap();
+        std::fs::write(&config_path, CONFIG).unwrap();
+        (credentials_path, config_path)
+    }
+
+    fn value<'a>(values: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+        values.get(key).map(String::as_str)
+    }
+
+    #[test]
+    fn default_and_named_profiles() {
+        let (credentials_path, config_path) = aws_files("profiles");
+
+        let default = profile_values(&credentials_path, &config_path, "default").unwrap();
+        assert_eq!(value(&default, "aws_access_key_id"), Some("DEFAULTKEY"));
+        assert_eq!(
+            value(&default, "aws_secret_access_key"),
+            Some("defaultsecret")
+        );
+        assert_eq!(value(&default, "region"), Some("us-east-1"));
+        assert_eq!(value(&default, "aws_session_token"), None);
+
+        // named profiles are prefixed in the config file, and the credentials file wins
+        let staging = profile_values(&credentials_path, &config_path, "staging").unwrap();
+        assert_eq!(value(&staging, "aws_access_key_id"), Some("STAGINGKEY"));
+        assert_eq!(value(&staging, "aws_session_token"), Some("stagingtoken"));
+        assert_eq!(value(&staging, "region"), Some("eu-west-1"));
+        assert_eq!(
+            value(&staging, "endpoint_url"),
+            Some("http://localhost:9000")
+        );
+
+        let config_only = profile_values(&credentials_path, &config_path, "config-only").unwrap();
+        assert_eq!(value(&config_only, "aws_access_key_id"), Some("CONFIGKEY"));
+
+        std::fs::remove_dir_all(credentials_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let (credentials_path, _) = aws_files("comments");
+
+        let default = read_profile(&credentials_path, "default").unwrap().unwrap();
+        assert_eq!(default.len(), 2);
+        assert!(default.keys().all(|key| !key.starts_with('#')));
+        assert!(read_profile(&credentials_path, "# keys of every profile")
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(credentials_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_profiles_and_keys() {
+        let (credentials_path, config_path) = aws_files("missing");
+
+        assert!(matches!(
+            profile_values(&credentials_path, &config_path, "production"),
+            Err(S3Error::MissingProfile(name)) if name == "production"
+        ));
+        // the config file only has a section for the prefixed name
+        assert!(read_profile(&config_path, "staging").unwrap().is_none());
+        // a missing file has no profiles
+        assert!(read_profile(&config_path.with_file_name("none"), "default")
+            .unwrap()
+            .is_none());
+
+        let no_secret = profile_values(&credentials_path, &config_path, "no-secret").unwrap();
+        assert_eq!(value(&no_secret, "aws_access_key_id"), Some("NOSECRETKEY"));
+        assert_eq!(value(&no_secret, "aws_secret_access_key"), None);
+
+        // no other test reads the shared files
+        env::set_var("AWS_SHARED_CREDENTIALS_FILE", &credentials_path);
+        env::set_var("AWS_CONFIG_FILE", &config_path);
+        assert!(matches!(
+            profile_builder("no-secret"),
+            Err(S3Error::MissingCredentials(name)) if name == "no-secret"
+        ));
+        assert!(profile_builder("staging").is_ok());
+
+        std::fs::remove_dir_all(credentials_path.parent().unwrap()).unwrap();
+    }
 }
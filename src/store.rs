@@ -1,20 +1,59 @@
 use anyhow::Context;
 use bytes::Bytes;
 use decoder::handle_buf;
+use futures::TryStreamExt;
 use object_store::{
-    aws::AmazonS3Builder, gcp::GoogleCloudStorageBuilder, http::HttpBuilder,
-    local::LocalFileSystem, path::Path, ClientOptions, ObjectStore,
+    gcp::GoogleCloudStorageBuilder, http::HttpBuilder, local::LocalFileSystem, path::Path,
+    ClientOptions, GetResult, ObjectMeta, ObjectStore, RetryConfig,
+};
+use rand::Rng;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use std::sync::Arc;
 use thiserror::Error;
 use url::Url;
 
 use sf_protos::ethereum::r#type::v2::Block;
 
+use crate::{
+    azure::AzureOptions, cache::BundleCache, layout::BundleLayout, memory::named_memory_store,
+    metrics::metrics, s3::S3Options,
+};
+
+/// default number of attempts at reading a file from the store
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// default delay before the first retry of a read
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// default upper bound of the delay between two reads
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// retry configuration of every object_store client. Reads are retried by [`Store`] with its
+/// [`RetryOptions`], retries of the client itself would multiply the attempts and the delays
+pub(crate) fn client_retry_config() -> RetryConfig {
+    RetryConfig {
+        max_retries: 0,
+        ..Default::default()
+    }
+}
+
+/// creates a store for `store_url`, with cloud credentials taken from the environment
 pub fn new<S: AsRef<str>>(
     store_url: S,
-    decompress: bool,
     compatible: Option<String>,
+) -> Result<Store, anyhow::Error> {
+    new_with_options(store_url, compatible, &StoreOptions::default())
+}
+
+/// creates a store for `store_url`, configured with the options of its kind of store. S3
+/// compatible stores, with `compatible` set to `s3`, take the S3 options
+pub fn new_with_options<S: AsRef<str>>(
+    store_url: S,
+    compatible: Option<String>,
+    options: &StoreOptions,
 ) -> Result<Store, anyhow::Error> {
     let store_url = store_url.as_ref();
     let url = match Url::parse(store_url) {
@@ -49,48 +88,65 @@ pub fn new<S: AsRef<str>>(
                 None => return Err(anyhow::anyhow!("invalid url format")),
             };
 
-            let store: Arc<dyn ObjectStore> = match compatible.as_deref() {
+            let (store, base): (Arc<dyn ObjectStore>, String) = match compatible.as_deref() {
                 Some("s3") => {
-                    let s3_store = AmazonS3Builder::new()
-                        .with_endpoint(endpoint.to_string())
-                        .with_bucket_name(url.path()[1..].to_string())
-                        .with_allow_http(scheme == "http")
-                        .with_access_key_id("any")
-                        .with_secret_access_key("any")
-                        .build()
-                        .context("Failed to build S3 store")?;
+                    // the first segment of the path is the bucket, the rest is the base path
+                    let (bucket, base) = base_path.split_once('/').unwrap_or((&base_path, ""));
+                    let s3_store = S3Options {
+                        endpoint: Some(endpoint),
+                        ..options.s3.clone()
+                    }
+                    .builder(bucket)?
+                    .build()
+                    .context("Failed to build S3 store")?;
 
-                    Arc::new(s3_store) as Arc<dyn ObjectStore>
+                    (Arc::new(s3_store), base.to_string())
                 }
                 _ => {
                     // Fallback to the HttpBuilder
                     let http_store = HttpBuilder::new()
                         .with_url(endpoint.to_string())
                         .with_client_options(ClientOptions::new().with_allow_http(scheme == "http"))
+                        .with_retry(client_retry_config())
                         .build()
                         .context("Failed to build HTTP store")?;
 
-                    Arc::new(http_store) as Arc<dyn ObjectStore>
+                    (Arc::new(http_store), "".to_string())
                 }
             };
 
             Ok(Store {
-                store: Arc::new(store),
-                base: "".to_string(),
-                decompress,
+                store,
+                base,
+                backend: match compatible.as_deref() {
+                    Some("s3") => "s3",
+                    _ => "http",
+                },
+                retry: RetryOptions::default(),
+                url: url.to_string(),
+                cache: None,
+                layout: BundleLayout::default(),
+                prefers_uncompressed: Arc::default(),
             })
         }
         "s3" => {
             let bucket: &str = url.host_str().ok_or_else(|| anyhow::anyhow!("No bucket"))?;
 
-            let store = AmazonS3Builder::new()
-                .with_bucket_name(bucket.to_string())
-                .build()?;
+            let store = options
+                .s3
+                .builder(bucket)?
+                .build()
+                .context("Failed to build S3 store")?;
 
             Ok(Store {
                 store: Arc::new(store),
                 base: base_path,
-                decompress,
+                backend: "s3",
+                retry: RetryOptions::default(),
+                url: url.to_string(),
+                cache: None,
+                layout: BundleLayout::default(),
+                prefers_uncompressed: Arc::default(),
             })
         }
         "gs" => {
@@ -98,39 +154,279 @@ pub fn new<S: AsRef<str>>(
 
             let store = GoogleCloudStorageBuilder::new()
                 .with_bucket_name(bucket.to_string())
+                .with_retry(client_retry_config())
                 .build()?;
 
             Ok(Store {
                 store: Arc::new(store),
                 base: base_path,
-                decompress,
+                backend: "gcs",
+                retry: RetryOptions::default(),
+                url: url.to_string(),
+                cache: None,
+                layout: BundleLayout::default(),
+                prefers_uncompressed: Arc::default(),
+            })
+        }
+        "az" | "abfs" | "abfss" => {
+            let store = options
+                .azure
+                .builder(url.as_str())
+                .build()
+                .context("Failed to build Azure store")?;
+
+            Ok(Store {
+                store: Arc::new(store),
+                base: base_path,
+                backend: "azure",
+                retry: RetryOptions::default(),
+                url: url.to_string(),
+                cache: None,
+                layout: BundleLayout::default(),
+                prefers_uncompressed: Arc::default(),
             })
         }
+        "memory" => Ok(Store {
+            store: named_memory_store(url.host_str().unwrap_or_default()),
+            base: base_path,
+            backend: "memory",
+            retry: RetryOptions::default(),
+            url: url.to_string(),
+            cache: None,
+            layout: BundleLayout::default(),
+            prefers_uncompressed: Arc::default(),
+        }),
         "file" => {
             let store = LocalFileSystem::new_with_prefix(url.path()).context("new local store")?;
 
             Ok(Store {
                 store: Arc::new(store),
                 base: "".to_string(),
-                decompress,
+                backend: "file",
+                retry: RetryOptions::default(),
+                url: url.to_string(),
+                cache: None,
+                layout: BundleLayout::default(),
+                prefers_uncompressed: Arc::default(),
             })
         }
         _ => Err(anyhow::anyhow!("Unsupported scheme: {}", url.scheme()))?,
     }
 }
 
+/// Configuration of the cloud stores, by kind of store.
+#[derive(Debug, Clone, Default)]
+pub struct StoreOptions {
+    pub s3: S3Options,
+    pub azure: AzureOptions,
+}
+
 #[derive(Clone)]
 pub struct Store {
     store: Arc<dyn ObjectStore>,
     base: String,
-    decompress: bool,
+    /// kind of object store, used to label metrics
+    backend: &'static str,
+    retry: RetryOptions,
+    /// URL the store was created from, which keys its files in the cache
+    url: String,
+    cache: Option<Arc<BundleCache>>,
+    layout: BundleLayout,
+    /// whether bundles are looked for without the `.zst` extension first, after the last bundle
+    /// was only found that way. Shared by clones, so they all learn which files the store holds
+    prefers_uncompressed: Arc<AtomicBool>,
 }
 
 impl Store {
+    /// wraps any object store, such as an [`InMemory`](object_store::memory::InMemory) one, with
+    /// bundle files read from its root.
+    ///
+    /// `url` keys the files of the store in the cache, so stores sharing a cache need different
+    /// ones unless they hold the same files
+    pub fn from_object_store(store: Arc<dyn ObjectStore>, url: impl Into<String>) -> Self {
+        Store {
+            url: url.into(),
+            store,
+            base: "".to_string(),
+            backend: "custom",
+            retry: RetryOptions::default(),
+            cache: None,
+            layout: BundleLayout::default(),
+            prefers_uncompressed: Arc::default(),
+        }
+    }
+
     pub async fn read_blocks(&self, path: String) -> Result<Vec<Block>, ReadError> {
-        let content = self.store.get(&self.join_path(path)).await?;
-        let bytes: Bytes = content.bytes().await.unwrap();
-        handle_from_bytes(bytes, self.decompress).await
+        let metrics = metrics();
+
+        let path = self.join_path(path);
+        let max_attempts = self.retry.max_attempts();
+        let mut attempt = 1;
+
+        let bytes = loop {
+            let fetch_timer = metrics
+                .store_latency_seconds
+                .with_label_values(&[self.backend])
+                .start_timer();
+            let result = self.fetch(&path).await;
+            fetch_timer.observe_duration();
+
+            match result {
+                Ok(bytes) => break bytes,
+                Err(e) if is_transient(&e) && attempt < max_attempts => {
+                    let backoff = self.retry.backoff(attempt);
+                    log::warn!(
+                        "Error fetching {} (attempt {}/{}), retrying in {:?}: {}",
+                        path,
+                        attempt,
+                        max_attempts,
+                        backoff,
+                        e
+                    );
+                    metrics
+                        .store_retries
+                        .with_label_values(&[self.backend])
+                        .inc();
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) if is_transient(&e) => {
+                    return Err(ReadError::RetriesExhausted {
+                        attempts: attempt,
+                        source: e,
+                    })
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        metrics
+            .bundles_fetched
+            .with_label_values(&[self.backend])
+            .inc();
+
+        let decode_timer = metrics.decode_seconds.start_timer();
+        let blocks = decode_bundle(&bytes);
+        decode_timer.observe_duration();
+
+        blocks
+    }
+
+    /// reads the bundle starting at `bundle_start`, from its compressed or uncompressed file,
+    /// whichever the store holds. Returns the name of the file read, or of the one looked for
+    /// first when neither is found, along with its blocks
+    pub async fn read_bundle(&self, bundle_start: u64) -> (String, Result<Vec<Block>, ReadError>) {
+        let compressed = !self.prefers_uncompressed.load(Ordering::Relaxed);
+        let file_name = self.layout.file_name(bundle_start, compressed);
+
+        match self.read_blocks(file_name.clone()).await {
+            Err(e) if e.is_not_found() => {
+                let other_name = self.layout.file_name(bundle_start, !compressed);
+                match self.read_blocks(other_name.clone()).await {
+                    Err(other_e) if other_e.is_not_found() => (file_name, Err(e)),
+                    result => {
+                        // the next bundles are likely stored the same way
+                        self.prefers_uncompressed
+                            .store(compressed, Ordering::Relaxed);
+                        (other_name, result)
+                    }
+                }
+            }
+            result => (file_name, result),
+        }
+    }
+
+    /// replaces the retry policy of reads from this store
+    pub fn with_retry_options(mut self, retry: RetryOptions) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// replaces the layout the bundle files of this store follow
+    pub fn with_layout(mut self, layout: BundleLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// URL the store was created from
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// how blocks are split into bundle files in this store, and where those files are
+    pub fn layout(&self) -> &BundleLayout {
+        &self.layout
+    }
+
+    /// reads go through `cache` when one is given, and only reach the store for files that are
+    /// not cached or have changed since
+    pub fn with_cache(mut self, cache: Option<Arc<BundleCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// reads an object from the cache, or downloads it. Any request to the store may fail with a
+    /// transient error
+    async fn fetch(&self, path: &Path) -> Result<Bytes, object_store::Error> {
+        let metrics = metrics();
+
+        let Some(cache) = &self.cache else {
+            let result = self.store.get(path).await?;
+            return self.download(result).await;
+        };
+
+        // the current size and ETag of the object tell whether the cached copy is stale
+        let meta = self.store.head(path).await?;
+        match cache.get(&self.url, path.as_ref(), &meta).await {
+            Ok(Some(bytes)) => {
+                metrics.cache_hits.with_label_values(&[self.backend]).inc();
+                return Ok(bytes);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Error reading {} from the cache: {}", path, e),
+        }
+        metrics
+            .cache_misses
+            .with_label_values(&[self.backend])
+            .inc();
+
+        let result = self.store.get(path).await?;
+        // the object may have changed since the HEAD, the GET tells what was downloaded
+        let meta = result.meta.clone();
+        let bytes = self.download(result).await?;
+        if let Err(e) = cache.put(&self.url, path.as_ref(), &meta, &bytes).await {
+            log::warn!("Error writing {} to the cache: {}", path, e);
+        }
+        Ok(bytes)
+    }
+
+    async fn download(&self, result: GetResult) -> Result<Bytes, object_store::Error> {
+        let bytes = result.bytes().await?;
+        metrics()
+            .bytes_downloaded
+            .with_label_values(&[self.backend])
+            .inc_by(bytes.len() as u64);
+        Ok(bytes)
+    }
+
+    /// every file under the store's base path, with its path relative to the base path and its size
+    pub async fn list_files(&self) -> Result<Vec<(String, usize)>, ReadError> {
+        let base = Path::from(self.base.as_str());
+        let prefix = (!self.base.is_empty()).then_some(&base);
+
+        let objects: Vec<ObjectMeta> = self.store.list(prefix).try_collect().await?;
+        Ok(objects
+            .into_iter()
+            .filter_map(|meta| {
+                let relative_path = meta
+                    .location
+                    .prefix_match(&base)?
+                    .map(|part| part.as_ref().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Some((relative_path, meta.size))
+            })
+            .collect())
     }
 
     fn join_path(&self, path: String) -> Path {
@@ -144,22 +440,131 @@ pub enum ReadError {
     NotFound(String),
     #[error("Storage error: {0}")]
     Storage(#[from] object_store::Error),
+    #[error("Storage error after {attempts} attempts: {source}")]
+    RetriesExhausted {
+        attempts: u32,
+        source: object_store::Error,
+    },
     #[error("Decode error: {0}")]
     DecodeError(String), // Or directly use DecodeError if it implements `std::error::Error`
+    #[error("Unknown format: neither a dbin file nor a zstd compressed one")]
+    UnknownFormat,
+}
+
+impl ReadError {
+    /// whether the file read is not in the store
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            ReadError::NotFound(_) | ReadError::Storage(object_store::Error::NotFound { .. })
+        )
+    }
+}
+
+/// Retry policy for transient store errors, with exponential backoff and jitter.
+#[derive(Debug, Clone, Default)]
+pub struct RetryOptions {
+    /// attempts at reading a file, including the first one
+    pub max_attempts: Option<u32>,
+    /// delay before the first retry. It doubles on every retry
+    pub initial_backoff: Option<Duration>,
+    /// upper bound of the delay between two attempts
+    pub max_backoff: Option<Duration>,
+    /// whether each delay is drawn at random between half and all of its value
+    pub jitter: Option<bool>,
+}
+
+impl RetryOptions {
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1)
+    }
+
+    pub fn initial_backoff(&self) -> Duration {
+        self.initial_backoff.unwrap_or(DEFAULT_INITIAL_BACKOFF)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        self.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF)
+    }
+
+    pub fn jitter(&self) -> bool {
+        self.jitter.unwrap_or(true)
+    }
+
+    /// delay to wait after the given failed attempt, counting from 1
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff()
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff());
+
+        if self.jitter() {
+            rand::thread_rng().gen_range(backoff / 2..=backoff)
+        } else {
+            backoff
+        }
+    }
 }
 
-pub struct ReadOptions {
-    pub decompress: Option<bool>,
+/// whether a store error may go away on its own.
+///
+/// Server errors (HTTP 5xx), timeouts, failed connections and bodies cut off mid-download are
+/// transient. Client errors such as 400, 401 or 403, typed errors such as not found, and local
+/// file errors are permanent, and are returned at once.
+fn is_transient(error: &object_store::Error) -> bool {
+    let object_store::Error::Generic { source, .. } = error else {
+        return false;
+    };
+
+    let mut next: Option<&(dyn std::error::Error + 'static)> = Some(source.as_ref());
+    while let Some(e) = next {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout()
+                || e.is_connect()
+                || e.is_body()
+                || e.status().is_some_and(|status| status.is_server_error())
+            {
+                return true;
+            }
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::BrokenPipe
+            ) {
+                return true;
+            }
+        }
+        next = e.source();
+    }
+    false
 }
 
-impl ReadOptions {
-    pub fn decompress(&self) -> bool {
-        self.decompress.unwrap_or(true)
+/// first bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// first bytes of a dbin file
+const DBIN_MAGIC: &[u8] = b"dbin";
+
+/// whether `bytes` are zstd compressed, `None` when they are not a dbin file either
+pub fn is_compressed(bytes: &[u8]) -> Option<bool> {
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        Some(true)
+    } else if bytes.starts_with(DBIN_MAGIC) {
+        Some(false)
+    } else {
+        None
     }
 }
 
-async fn handle_from_bytes(bytes: Bytes, decompress: bool) -> Result<Vec<Block>, ReadError> {
-    handle_buf(bytes.as_ref(), Some(decompress)).map_err(|e| ReadError::DecodeError(e.to_string()))
+/// blocks of a bundle file, decompressed first if it is compressed
+pub fn decode_bundle(bytes: &[u8]) -> Result<Vec<Block>, ReadError> {
+    let compressed = is_compressed(bytes).ok_or(ReadError::UnknownFormat)?;
+    handle_buf(bytes, Some(compressed)).map_err(|e| ReadError::DecodeError(e.to_string()))
 }
 
 // async fn fake_handle_from_stream(
@@ -195,3 +600,122 @@ async fn handle_from_bytes(bytes: Bytes, decompress: bool) -> Result<Vec<Block>,
 //     )
 //     .expect("demo code, deal with error nicely"))
 // }
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn generic(source: impl std::error::Error + Send + Sync + 'static) -> object_store::Error {
+        object_store::Error::Generic {
+            store: "test",
+            source: Box::new(source),
+        }
+    }
+
+    /// the error of a GET answered with `status` by a local server
+    async fn status_error(status: &str) -> object_store::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let response = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+        generic(response.error_for_status().unwrap_err())
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_transient() {
+        assert!(is_transient(&status_error("503 Service Unavailable").await));
+        assert!(is_transient(
+            &status_error("500 Internal Server Error").await
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        assert!(!is_transient(&status_error("400 Bad Request").await));
+        assert!(!is_transient(&status_error("401 Unauthorized").await));
+        assert!(!is_transient(&status_error("403 Forbidden").await));
+    }
+
+    #[tokio::test]
+    async fn refused_connections_are_transient() {
+        // bind then drop a listener, so nothing listens on its port
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = reqwest::get(format!("http://{}/", addr)).await.unwrap_err();
+        assert!(is_transient(&generic(error)));
+    }
+
+    #[test]
+    fn network_io_errors_are_transient() {
+        for kind in [
+            std::io::ErrorKind::TimedOut,
+            std::io::ErrorKind::ConnectionReset,
+            std::io::ErrorKind::BrokenPipe,
+        ] {
+            assert!(is_transient(&generic(std::io::Error::from(kind))));
+        }
+    }
+
+    #[test]
+    fn zstd_frames_are_compressed() {
+        let compressed = zstd::encode_all(&b"dbin"[..], 0).unwrap();
+        assert_eq!(is_compressed(&compressed), Some(true));
+        assert_eq!(is_compressed(&ZSTD_MAGIC), Some(true));
+
+        let fixture = std::fs::read("tests/compressed/0000000000.dbin.zst").unwrap();
+        assert_eq!(is_compressed(&fixture), Some(true));
+    }
+
+    #[test]
+    fn dbin_files_are_uncompressed() {
+        assert_eq!(is_compressed(b"dbin\x01eth"), Some(false));
+
+        let fixture = std::fs::read("tests/ethereum_firehose_first_8200/0000000000.dbin").unwrap();
+        assert_eq!(is_compressed(&fixture), Some(false));
+    }
+
+    #[test]
+    fn other_bytes_are_unknown() {
+        assert_eq!(is_compressed(b""), None);
+        // too short to hold either magic
+        assert_eq!(is_compressed(b"dbi"), None);
+        assert_eq!(is_compressed(&ZSTD_MAGIC[..3]), None);
+        assert_eq!(is_compressed(b"DBIN"), None);
+        // gzip
+        assert_eq!(is_compressed(&[0x1f, 0x8b, 0x08, 0x00]), None);
+        // the zstd magic in the wrong byte order
+        assert_eq!(is_compressed(&[0xfd, 0x2f, 0xb5, 0x28]), None);
+
+        assert!(matches!(
+            decode_bundle(b"not a bundle"),
+            Err(ReadError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn other_errors_are_permanent() {
+        assert!(!is_transient(&generic(std::io::Error::from(
+            std::io::ErrorKind::PermissionDenied
+        ))));
+        assert!(!is_transient(&generic(std::fmt::Error)));
+        assert!(!is_transient(&object_store::Error::NotFound {
+            path: "0000000000.dbin.zst".to_string(),
+            source: Box::new(std::fmt::Error),
+        }));
+    }
+}
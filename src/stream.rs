@@ -1,76 +1,313 @@
-// use std::{
-//     error::Error,
-//     io::{BufRead, Read, Write},
-// };
-
-// use bincode::{deserialize, ErrorKind};
-// use header_accumulator::{
-//     errors::EraValidateError,
-//     types::ExtHeaderRecord,
-//     utils::{compute_epoch_accumulator, MAX_EPOCH_SIZE},
-// };
-// use trin_validation::accumulator::MasterAccumulator;
-
-// pub fn stream_validation<R: Read + BufRead, W: Write>(
-//     master_accumulator: MasterAccumulator,
-//     mut reader: R,
-//     mut writer: W,
-// ) -> Result<(), EraValidateError> {
-//     let mut header_records = Vec::new();
-//     let mut append_flag = false;
-//     let mut buf = String::new();
-
-//     while let Ok(hr) = receive_message(&mut reader) {
-//         buf.clear();
-
-//         log::info!("{:?}", hr.block_hash);
-//         if header_records.len() == 0 {
-//             if hr.block_number % MAX_EPOCH_SIZE as u64 == 0 {
-//                 let epoch = hr.block_number as usize / MAX_EPOCH_SIZE;
-//                 log::info!("Validating epoch: {}", epoch);
-//                 append_flag = true;
-//             }
-//         }
-//         if append_flag == true {
-//             header_records.push(hr);
-//         }
-
-//         if header_records.len() == MAX_EPOCH_SIZE {
-//             let epoch = hr.block_number as usize / MAX_EPOCH_SIZE;
-//             let epoch_accumulator = compute_epoch_accumulator(header_records)?;
-//             if epoch_accumulator.tree_hash_root().0 != master_accumulator.historical_epochs[epoch].0
-//             {
-//                 Err(EraValidateError::EraAccumulatorMismatch)?;
-//             }
-//             log::info!("Validated epoch: {}", epoch);
-//             writer
-//                 .write_all(format!("Validated epoch: {}\n", epoch).as_bytes())
-//                 .map_err(|_| EraValidateError::JsonError)?;
-//             header_records.clear();
-//         }
-//     }
-
-//     log::info!("Read {} block headers from stdin", header_records.len());
-//     Ok(())
-// }
-
-// // TODO: this functionality should be moved to flat_head
-// fn receive_message<R: Read>(reader: &mut R) -> Result<ExtHeaderRecord, Box<dyn Error>> {
-//     let mut size_buf = [0u8; 4];
-//     if reader.read_exact(&mut size_buf).is_err() {
-//         return Err(Box::new(ErrorKind::Io(std::io::Error::new(
-//             std::io::ErrorKind::UnexpectedEof,
-//             "Failed to read size",
-//         ))));
-//     }
-
-//     let size = u32::from_be_bytes(size_buf) as usize;
-//     println!("size: {:?}", size);
-
-//     let mut buf = vec![0u8; size];
-//     reader.read_exact(&mut buf)?;
-//     let hr: ExtHeaderRecord = deserialize(&buf)?;
-
-//     println!(" decoding {:?}", hr);
-//     Ok(hr)
-// }
+use std::io::{Read, Write};
+
+use header_accumulator::{errors::EraValidateError, types::ExtHeaderRecord};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use trin_validation::accumulator::PreMergeAccumulator;
+
+use crate::era_verifier::{validate_epoch_headers, FINAL_EPOCH, MAX_EPOCH_SIZE, MERGE_BLOCK};
+
+/// largest message accepted from the stream. A bincode header record is under 100 bytes, so a
+/// larger length prefix can only come from a corrupt or misaligned stream, and is rejected
+/// before a buffer of that size is allocated
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum StreamValidateError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Header record decode error: {0}")]
+    Decode(#[from] bincode::Error),
+    #[error("Epoch {epoch} failed validation: {source}")]
+    Validation {
+        epoch: usize,
+        source: EraValidateError,
+    },
+    #[error("Expected block {expected} but received block {received}")]
+    UnexpectedBlock { expected: u64, received: u64 },
+    #[error("Epoch {0} is past the merge, the last pre-merge epoch is {FINAL_EPOCH}")]
+    PostMergeEpoch(usize),
+    #[error("Message of {0} bytes is larger than the {MAX_MESSAGE_SIZE} bytes allowed for a header record")]
+    MessageTooLarge(usize),
+}
+
+/// Validates a stream of length-prefixed bincode `ExtHeaderRecord`s against the master accumulator.
+///
+/// Every message is a big-endian `u32` length followed by that many bytes of bincode.
+/// Headers before the first epoch boundary are skipped, then each complete epoch of
+/// `MAX_EPOCH_SIZE` headers is validated and reported to `writer` as it is completed. The final
+/// pre-merge epoch is validated once the block right before the merge has been received.
+/// Returns the validated epochs once the reader is exhausted.
+pub fn stream_validation<R: Read, W: Write>(
+    master_accumulator: PreMergeAccumulator,
+    mut reader: R,
+    mut writer: W,
+) -> Result<Vec<usize>, StreamValidateError> {
+    let mut collector = EpochCollector::new(master_accumulator);
+
+    while let Some(hr) = receive_message(&mut reader)? {
+        if let Some(epoch) = collector.push(hr)? {
+            writer.write_all(format!("Validated epoch: {}\n", epoch).as_bytes())?;
+        }
+    }
+
+    collector.finish()
+}
+
+/// Async counterpart of [`stream_validation`], for readers such as `tokio::io::stdin` or a socket.
+pub async fn stream_validation_async<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    master_accumulator: PreMergeAccumulator,
+    mut reader: R,
+    mut writer: W,
+) -> Result<Vec<usize>, StreamValidateError> {
+    let mut collector = EpochCollector::new(master_accumulator);
+
+    while let Some(hr) = receive_message_async(&mut reader).await? {
+        if let Some(epoch) = collector.push(hr)? {
+            writer
+                .write_all(format!("Validated epoch: {}\n", epoch).as_bytes())
+                .await?;
+            writer.flush().await?;
+        }
+    }
+
+    collector.finish()
+}
+
+/// Groups incoming header records into epochs and validates each one once it is complete.
+struct EpochCollector {
+    master_accumulator: PreMergeAccumulator,
+    header_records: Vec<ExtHeaderRecord>,
+    validated_epochs: Vec<usize>,
+}
+
+impl EpochCollector {
+    fn new(master_accumulator: PreMergeAccumulator) -> Self {
+        EpochCollector {
+            master_accumulator,
+            header_records: Vec::with_capacity(MAX_EPOCH_SIZE),
+            validated_epochs: Vec::new(),
+        }
+    }
+
+    /// returns the epoch that was validated, if `hr` completed one
+    fn push(&mut self, hr: ExtHeaderRecord) -> Result<Option<usize>, StreamValidateError> {
+        match self.header_records.last() {
+            // wait for the start of an epoch before collecting anything
+            None if hr.block_number % MAX_EPOCH_SIZE as u64 != 0 => return Ok(None),
+            None => {
+                let epoch = hr.block_number as usize / MAX_EPOCH_SIZE;
+                if epoch > FINAL_EPOCH {
+                    return Err(StreamValidateError::PostMergeEpoch(epoch));
+                }
+                log::info!("Validating epoch: {}", epoch);
+            }
+            Some(last) if hr.block_number != last.block_number + 1 => {
+                return Err(StreamValidateError::UnexpectedBlock {
+                    expected: last.block_number + 1,
+                    received: hr.block_number,
+                });
+            }
+            Some(_) => {}
+        }
+
+        // the final pre-merge epoch is complete once the block before the merge arrives
+        let epoch_complete = self.header_records.len() + 1 == MAX_EPOCH_SIZE
+            || hr.block_number + 1 == MERGE_BLOCK as u64;
+        self.header_records.push(hr);
+
+        if !epoch_complete {
+            return Ok(None);
+        }
+
+        let epoch = self.header_records[0].block_number as usize / MAX_EPOCH_SIZE;
+        let header_records = std::mem::take(&mut self.header_records);
+        validate_epoch_headers(&self.master_accumulator, header_records, epoch, false)
+            .map_err(|source| StreamValidateError::Validation { epoch, source })?;
+
+        log::info!("Validated epoch: {}", epoch);
+        self.validated_epochs.push(epoch);
+        Ok(Some(epoch))
+    }
+
+    fn finish(self) -> Result<Vec<usize>, StreamValidateError> {
+        if !self.header_records.is_empty() {
+            log::warn!(
+                "Stream ended with {} headers of an incomplete epoch",
+                self.header_records.len()
+            );
+        }
+        Ok(self.validated_epochs)
+    }
+}
+
+/// reads one length-prefixed header record, or `None` once the reader is exhausted
+fn receive_message<R: Read>(
+    reader: &mut R,
+) -> Result<Option<ExtHeaderRecord>, StreamValidateError> {
+    let mut size_buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < size_buf.len() {
+        match reader.read(&mut size_buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let Some(size) = message_size(size_buf, filled)? else {
+        return Ok(None);
+    };
+    let mut buf = vec![0u8; size];
+    reader.read_exact(&mut buf)?;
+
+    Ok(Some(bincode::deserialize(&buf)?))
+}
+
+async fn receive_message_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<ExtHeaderRecord>, StreamValidateError> {
+    let mut size_buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < size_buf.len() {
+        match reader.read(&mut size_buf[filled..]).await {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let Some(size) = message_size(size_buf, filled)? else {
+        return Ok(None);
+    };
+    let mut buf = vec![0u8; size];
+    reader.read_exact(&mut buf).await?;
+
+    Ok(Some(bincode::deserialize(&buf)?))
+}
+
+/// size of the message announced by a length prefix of which `filled` bytes could be read.
+/// The stream may only end between two messages
+fn message_size(size_buf: [u8; 4], filled: usize) -> Result<Option<usize>, StreamValidateError> {
+    match filled {
+        0 => Ok(None),
+        4 => match u32::from_be_bytes(size_buf) as usize {
+            size if size > MAX_MESSAGE_SIZE => Err(StreamValidateError::MessageTooLarge(size)),
+            size => Ok(Some(size)),
+        },
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("stream ended after {} bytes of a length prefix", filled),
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::store::decode_bundle;
+
+    /// length-prefixed header records of the first 8200 blocks, from the fixture flat files
+    fn fixture_stream() -> Vec<u8> {
+        let mut stream = Vec::new();
+        for bundle_start in (0..8200).step_by(100) {
+            let path = format!(
+                "tests/ethereum_firehose_first_8200/{:010}.dbin",
+                bundle_start
+            );
+            let blocks = decode_bundle(&std::fs::read(path).unwrap()).unwrap();
+            for block in blocks.iter() {
+                let message =
+                    bincode::serialize(&ExtHeaderRecord::try_from(block).unwrap()).unwrap();
+                stream.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                stream.extend_from_slice(&message);
+            }
+        }
+        stream
+    }
+
+    #[test]
+    fn fixture_epoch_validates() {
+        let mut output = Vec::new();
+        let epochs = stream_validation(
+            PreMergeAccumulator::default(),
+            Cursor::new(fixture_stream()),
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(epochs, vec![0]);
+        assert_eq!(String::from_utf8(output).unwrap(), "Validated epoch: 0\n");
+    }
+
+    #[tokio::test]
+    async fn fixture_epoch_validates_async() {
+        let mut output = Vec::new();
+        let epochs = stream_validation_async(
+            PreMergeAccumulator::default(),
+            Cursor::new(fixture_stream()),
+            &mut output,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(epochs, vec![0]);
+        assert_eq!(String::from_utf8(output).unwrap(), "Validated epoch: 0\n");
+    }
+
+    #[test]
+    fn empty_stream_ends_cleanly() {
+        assert!(receive_message(&mut Cursor::new(Vec::new()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn partial_length_prefix_is_an_error() {
+        for len in 1..4 {
+            match receive_message(&mut Cursor::new(vec![0u8; len])) {
+                Err(StreamValidateError::Io(e)) => {
+                    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof)
+                }
+                other => panic!("unexpected result for {} bytes: {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_message_is_rejected_before_reading_it() {
+        let prefix = u32::MAX.to_be_bytes().to_vec();
+        assert!(matches!(
+            receive_message(&mut Cursor::new(prefix)),
+            Err(StreamValidateError::MessageTooLarge(size)) if size == u32::MAX as usize
+        ));
+    }
+
+    #[test]
+    fn truncated_message_is_an_error() {
+        let mut bytes = 10u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[0u8; 4]);
+        assert!(matches!(
+            receive_message(&mut Cursor::new(bytes)),
+            Err(StreamValidateError::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn async_reader_rejects_partial_length_prefix() {
+        let mut reader = Cursor::new(vec![0u8; 2]);
+        assert!(matches!(
+            receive_message_async(&mut reader).await,
+            Err(StreamValidateError::Io(_))
+        ));
+        assert!(receive_message_async(&mut Cursor::new(Vec::new()))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,453 @@
+use alloy_primitives::B256;
+use alloy_rlp::{BufMut, Encodable, Header};
+use alloy_trie::{HashBuilder, Nibbles, EMPTY_ROOT_HASH};
+use thiserror::Error;
+
+use crate::utils::read_hash;
+use sf_protos::ethereum::r#type::v2::{
+    transaction_trace::Type, AccessTuple, BigInt, Block, TransactionTrace,
+};
+
+/// chain id signed into typed transactions, which firehose does not store
+pub const MAINNET_CHAIN_ID: u64 = 1;
+
+#[derive(Error, Debug)]
+pub enum TransactionError {
+    #[error("Block {0} has no header")]
+    MissingHeader(u64),
+    #[error("Transaction {hash} has unsupported type {tx_type}")]
+    UnsupportedType { hash: B256, tx_type: i32 },
+    #[error("Block {block}: transactions root mismatch, header has {expected} but bodies give {computed}")]
+    Mismatch {
+        block: u64,
+        expected: B256,
+        computed: B256,
+    },
+    #[error("{field} is {len} bytes long instead of 32")]
+    InvalidHash { field: &'static str, len: usize },
+}
+
+/// rebuilds the transactions trie root of a block from its transaction traces
+pub fn transactions_root(block: &Block) -> Result<B256, TransactionError> {
+    let encoded = block
+        .transaction_traces
+        .iter()
+        .map(encode_transaction)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ordered_trie_root(&encoded))
+}
+
+/// root of a trie keyed by the RLP-encoded index of each item, as the transactions and
+/// receipts tries of a block are
+pub(crate) fn ordered_trie_root(items: &[Vec<u8>]) -> B256 {
+    if items.is_empty() {
+        return EMPTY_ROOT_HASH;
+    }
+
+    // leaves must be added in key order, and index 0 encodes as 0x80, after 0x01..=0x7f
+    let mut builder = HashBuilder::default();
+    for i in 0..items.len() {
+        let index = adjust_index_for_rlp(i, items.len());
+        builder.add_leaf(Nibbles::unpack(alloy_rlp::encode(index)), &items[index]);
+    }
+    builder.root()
+}
+
+fn adjust_index_for_rlp(i: usize, len: usize) -> usize {
+    if i > 0x7f {
+        i
+    } else if i == 0x7f || i + 1 == len {
+        0
+    } else {
+        i + 1
+    }
+}
+
+/// compares the transactions root rebuilt from the block's transaction traces with the one in its header
+pub fn check_transactions_root(block: &Block) -> Result<(), TransactionError> {
+    let header = block
+        .header
+        .as_ref()
+        .ok_or(TransactionError::MissingHeader(block.number))?;
+
+    let computed = transactions_root(block)?;
+    let expected =
+        read_hash(&header.transactions_root).map_err(|len| TransactionError::InvalidHash {
+            field: "Transactions root",
+            len,
+        })?;
+
+    if computed != expected {
+        return Err(TransactionError::Mismatch {
+            block: block.number,
+            expected,
+            computed,
+        });
+    }
+
+    Ok(())
+}
+
+/// encodes a transaction the way it is stored in the transactions trie: plain RLP for legacy
+/// transactions, the type byte followed by the RLP payload for typed ones
+pub fn encode_transaction(trace: &TransactionTrace) -> Result<Vec<u8>, TransactionError> {
+    let hash = read_hash(&trace.hash).map_err(|len| TransactionError::InvalidHash {
+        field: "Transaction hash",
+        len,
+    })?;
+    let tx_type = Type::try_from(trace.r#type).map_err(|_| TransactionError::UnsupportedType {
+        hash,
+        tx_type: trace.r#type,
+    })?;
+
+    let mut payload = Vec::new();
+
+    match tx_type {
+        Type::TrxTypeLegacy => {
+            trace.nonce.encode(&mut payload);
+            encode_big_int(&trace.gas_price, &mut payload);
+            trace.gas_limit.encode(&mut payload);
+            trace.to.as_slice().encode(&mut payload);
+            encode_big_int(&trace.value, &mut payload);
+            trace.input.as_slice().encode(&mut payload);
+        }
+        Type::TrxTypeAccessList => {
+            MAINNET_CHAIN_ID.encode(&mut payload);
+            trace.nonce.encode(&mut payload);
+            encode_big_int(&trace.gas_price, &mut payload);
+            trace.gas_limit.encode(&mut payload);
+            trace.to.as_slice().encode(&mut payload);
+            encode_big_int(&trace.value, &mut payload);
+            trace.input.as_slice().encode(&mut payload);
+            encode_access_list(&trace.access_list, &mut payload);
+        }
+        Type::TrxTypeDynamicFee => {
+            MAINNET_CHAIN_ID.encode(&mut payload);
+            trace.nonce.encode(&mut payload);
+            encode_big_int(&trace.max_priority_fee_per_gas, &mut payload);
+            encode_big_int(&trace.max_fee_per_gas, &mut payload);
+            trace.gas_limit.encode(&mut payload);
+            trace.to.as_slice().encode(&mut payload);
+            encode_big_int(&trace.value, &mut payload);
+            trace.input.as_slice().encode(&mut payload);
+            encode_access_list(&trace.access_list, &mut payload);
+        }
+        Type::TrxTypeBlob => {
+            MAINNET_CHAIN_ID.encode(&mut payload);
+            trace.nonce.encode(&mut payload);
+            encode_big_int(&trace.max_priority_fee_per_gas, &mut payload);
+            encode_big_int(&trace.max_fee_per_gas, &mut payload);
+            trace.gas_limit.encode(&mut payload);
+            trace.to.as_slice().encode(&mut payload);
+            encode_big_int(&trace.value, &mut payload);
+            trace.input.as_slice().encode(&mut payload);
+            encode_access_list(&trace.access_list, &mut payload);
+            encode_big_int(&trace.blob_gas_fee_cap, &mut payload);
+            encode_list(
+                trace
+                    .blob_hashes
+                    .iter()
+                    .map(|hash| hash.as_slice())
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+                &mut payload,
+            );
+        }
+        _ => {
+            return Err(TransactionError::UnsupportedType {
+                hash,
+                tx_type: trace.r#type,
+            })
+        }
+    }
+
+    // `v` is the full EIP-155 value for legacy transactions and the y parity for typed ones
+    encode_uint_bytes(&trace.v, &mut payload);
+    encode_uint_bytes(&trace.r, &mut payload);
+    encode_uint_bytes(&trace.s, &mut payload);
+
+    let mut out = Vec::with_capacity(payload.len() + 5);
+    if tx_type != Type::TrxTypeLegacy {
+        out.push(trace.r#type as u8);
+    }
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&payload);
+
+    Ok(out)
+}
+
+fn encode_access_list(access_list: &[AccessTuple], out: &mut dyn BufMut) {
+    let mut payload = Vec::new();
+    for tuple in access_list {
+        let mut tuple_payload = Vec::new();
+        tuple.address.as_slice().encode(&mut tuple_payload);
+        encode_list(
+            tuple
+                .storage_keys
+                .iter()
+                .map(|key| key.as_slice())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            &mut tuple_payload,
+        );
+
+        Header {
+            list: true,
+            payload_length: tuple_payload.len(),
+        }
+        .encode(&mut payload);
+        payload.extend_from_slice(&tuple_payload);
+    }
+
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(out);
+    out.put_slice(&payload);
+}
+
+fn encode_list(items: &[&[u8]], out: &mut dyn BufMut) {
+    alloy_rlp::encode_list::<&[u8], [u8]>(items, out);
+}
+
+pub(crate) fn encode_big_int(value: &Option<BigInt>, out: &mut dyn BufMut) {
+    match value {
+        Some(value) => encode_uint_bytes(&value.bytes, out),
+        None => 0u64.encode(out),
+    }
+}
+
+/// encodes big-endian bytes as an RLP integer, which must not have leading zeros
+pub(crate) fn encode_uint_bytes(bytes: &[u8], out: &mut dyn BufMut) {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].encode(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{b256, hex, keccak256};
+    use sf_protos::ethereum::r#type::v2::BlockHeader;
+
+    use super::*;
+    use crate::store::decode_bundle;
+
+    /// transaction of the EIP-155 example, signed by the private key `0x4646…46`
+    const EIP155_TRANSACTION: [u8; 110] = hex!("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+    const EIP155_TRANSACTION_HASH: B256 =
+        b256!("33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788");
+
+    // typed transactions encoded by a separate RLP encoder and signed by the EIP-155 example key
+    const ACCESS_LIST_TRANSACTION: [u8; 224] = hex!("01f8dd010a8506fc23ac0082ea609435353535353535353535353535353535353535358084a9059cbbf872f85994dedededededededededededededededededededef842a00000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000001d694adadadadadadadadadadadadadadadadadadadadc080a076416c10274e7eca2b816926e90f2af3c107550a7a5ff64a7041fd21c2ac9f1da0389debb65e924086c38d91a6754b371636ec025b995b269baa88a2612ad86662");
+    const DYNAMIC_FEE_TRANSACTION: [u8; 237] = hex!("02f8ea010b8477359400850ba43b740082ea60943535353535353535353535353535353535353535880de0b6b3a764000084a9059cbbf872f85994dedededededededededededededededededededef842a00000000000000000000000000000000000000000000000000000000000000000a00000000000000000000000000000000000000000000000000000000000000001d694adadadadadadadadadadadadadadadadadadadadc080a0ca56143812f3d945bec8baf09d0844361e5af48ec3fa4e0c7d2b8f4d9d627909a037189062b7b4696370d11844b24e4573fc5aeb920d4db054193f42822e3b48a0");
+    const BLOB_TRANSACTION: [u8; 183] = hex!("03f8b4010c8477359400850ba43b74008252089435353535353535353535353535353535353535358080c084b2d05e00f842a00100000000000000000000000000000000000000000000000000000000000001a0010101010101010101010101010101010101010101010101010101010101010180a0aa8b7b6ce9fe467b850def874847d8fb45c64462bb4d11eb7d00decfd926666ca029ccb357df293236408ec146841b42acb818b03e03725092a32829cbcd073d5b");
+
+    fn big_int(value: u64) -> Option<BigInt> {
+        Some(BigInt {
+            bytes: value.to_be_bytes().to_vec(),
+        })
+    }
+
+    fn access_list() -> Vec<AccessTuple> {
+        vec![
+            AccessTuple {
+                address: vec![0xde; 20],
+                storage_keys: vec![B256::ZERO.to_vec(), B256::with_last_byte(1).to_vec()],
+            },
+            AccessTuple {
+                address: vec![0xad; 20],
+                storage_keys: vec![],
+            },
+        ]
+    }
+
+    /// traces of the legacy, access list, dynamic fee and blob transactions above, in that order
+    fn signed_traces() -> Vec<TransactionTrace> {
+        let trace = |tx_type: Type, nonce, raw: &[u8], r: &str, s: &str| TransactionTrace {
+            r#type: tx_type as i32,
+            hash: keccak256(raw).to_vec(),
+            nonce,
+            to: vec![0x35; 20],
+            r: hex::decode(r).unwrap(),
+            s: hex::decode(s).unwrap(),
+            ..Default::default()
+        };
+
+        vec![
+            TransactionTrace {
+                gas_price: big_int(20_000_000_000),
+                gas_limit: 21_000,
+                value: big_int(1_000_000_000_000_000_000),
+                v: vec![37],
+                ..trace(
+                    Type::TrxTypeLegacy,
+                    9,
+                    &EIP155_TRANSACTION,
+                    "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276",
+                    "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+                )
+            },
+            TransactionTrace {
+                gas_price: big_int(30_000_000_000),
+                gas_limit: 60_000,
+                input: vec![0xa9, 0x05, 0x9c, 0xbb],
+                access_list: access_list(),
+                ..trace(
+                    Type::TrxTypeAccessList,
+                    10,
+                    &ACCESS_LIST_TRANSACTION,
+                    "76416c10274e7eca2b816926e90f2af3c107550a7a5ff64a7041fd21c2ac9f1d",
+                    "389debb65e924086c38d91a6754b371636ec025b995b269baa88a2612ad86662",
+                )
+            },
+            TransactionTrace {
+                max_priority_fee_per_gas: big_int(2_000_000_000),
+                max_fee_per_gas: big_int(50_000_000_000),
+                gas_limit: 60_000,
+                value: big_int(1_000_000_000_000_000_000),
+                input: vec![0xa9, 0x05, 0x9c, 0xbb],
+                access_list: access_list(),
+                ..trace(
+                    Type::TrxTypeDynamicFee,
+                    11,
+                    &DYNAMIC_FEE_TRANSACTION,
+                    "ca56143812f3d945bec8baf09d0844361e5af48ec3fa4e0c7d2b8f4d9d627909",
+                    "37189062b7b4696370d11844b24e4573fc5aeb920d4db054193f42822e3b48a0",
+                )
+            },
+            TransactionTrace {
+                max_priority_fee_per_gas: big_int(2_000_000_000),
+                max_fee_per_gas: big_int(50_000_000_000),
+                gas_limit: 21_000,
+                blob_gas_fee_cap: big_int(3_000_000_000),
+                blob_hashes: vec![
+                    hex::decode("0100000000000000000000000000000000000000000000000000000000000001")
+                        .unwrap(),
+                    vec![0x01; 32],
+                ],
+                ..trace(
+                    Type::TrxTypeBlob,
+                    12,
+                    &BLOB_TRANSACTION,
+                    "aa8b7b6ce9fe467b850def874847d8fb45c64462bb4d11eb7d00decfd926666c",
+                    "29ccb357df293236408ec146841b42acb818b03e03725092a32829cbcd073d5b",
+                )
+            },
+        ]
+    }
+
+    #[test]
+    fn empty_trie_root() {
+        assert_eq!(ordered_trie_root(&[]), EMPTY_ROOT_HASH);
+    }
+
+    #[test]
+    fn single_item_trie_root() {
+        let item = vec![0xab; 40];
+
+        // a lone leaf at key rlp(0) = 0x80: [hex-prefix of the even leaf path, value]
+        let mut payload = Vec::new();
+        [0x20u8, 0x80].as_slice().encode(&mut payload);
+        item.as_slice().encode(&mut payload);
+        let mut node = Vec::new();
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut node);
+        node.extend_from_slice(&payload);
+
+        assert_eq!(ordered_trie_root(&[item]), keccak256(node));
+    }
+
+    #[test]
+    fn index_order_puts_zero_after_small_indexes() {
+        let order: Vec<_> = (0..4).map(|i| adjust_index_for_rlp(i, 4)).collect();
+        assert_eq!(order, vec![1, 2, 3, 0]);
+
+        let order: Vec<_> = (0x7e..0x82)
+            .map(|i| adjust_index_for_rlp(i, 0x100))
+            .collect();
+        assert_eq!(order, vec![0x7f, 0, 0x80, 0x81]);
+    }
+
+    #[test]
+    fn oversized_transaction_hash_is_an_error() {
+        let trace = TransactionTrace {
+            hash: vec![0x11; 33],
+            ..Default::default()
+        };
+        assert!(matches!(
+            encode_transaction(&trace),
+            Err(TransactionError::InvalidHash { len: 33, .. })
+        ));
+    }
+
+    #[test]
+    fn eip155_example_transaction() {
+        let trace = &signed_traces()[0];
+        let encoded = encode_transaction(trace).unwrap();
+        assert_eq!(encoded, EIP155_TRANSACTION);
+        assert_eq!(keccak256(&encoded), EIP155_TRANSACTION_HASH);
+    }
+
+    #[test]
+    fn typed_transactions_are_prefixed_with_their_type() {
+        let expected: [&[u8]; 3] = [
+            &ACCESS_LIST_TRANSACTION,
+            &DYNAMIC_FEE_TRANSACTION,
+            &BLOB_TRANSACTION,
+        ];
+        for (trace, expected) in signed_traces()[1..].iter().zip(expected) {
+            let encoded = encode_transaction(trace).unwrap();
+            assert_eq!(encoded, expected, "transaction type {}", trace.r#type);
+            // typed transactions are hashed with their type byte
+            assert_eq!(keccak256(&encoded).as_slice(), trace.hash);
+        }
+    }
+
+    #[test]
+    fn block_of_every_transaction_type() {
+        let transaction_traces = signed_traces();
+        let root = ordered_trie_root(&[
+            EIP155_TRANSACTION.to_vec(),
+            ACCESS_LIST_TRANSACTION.to_vec(),
+            DYNAMIC_FEE_TRANSACTION.to_vec(),
+            BLOB_TRANSACTION.to_vec(),
+        ]);
+        let mut block = Block {
+            number: 19_426_587,
+            header: Some(BlockHeader {
+                transactions_root: root.to_vec(),
+                ..Default::default()
+            }),
+            transaction_traces,
+            ..Default::default()
+        };
+        check_transactions_root(&block).unwrap();
+
+        // the root commits to the order of the transactions
+        block.transaction_traces.swap(1, 2);
+        assert!(matches!(
+            check_transactions_root(&block),
+            Err(TransactionError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn fixture_transactions_roots() {
+        let bytes = std::fs::read("tests/ethereum_firehose_first_8200/0000000000.dbin").unwrap();
+        let blocks = decode_bundle(&bytes).unwrap();
+        assert_eq!(blocks.len(), 100);
+
+        for block in blocks.iter() {
+            check_transactions_root(block).unwrap();
+            assert_eq!(transactions_root(block).unwrap(), EMPTY_ROOT_HASH);
+        }
+    }
+}
@@ -1,27 +1,65 @@
-use header_accumulator::epoch::MAX_EPOCH_SIZE;
+use alloy_primitives::B256;
+use header_accumulator::{epoch::MAX_EPOCH_SIZE, errors::EraValidateError};
 
-/// return the filenames of files to be fetched, between a starting and an ending epoch
-pub fn gen_dbin_filenames(start: u64, end: u64, compressed: Option<bool>) -> Vec<String> {
-    let mut filenames = Vec::new();
+use crate::layout::BundleLayout;
 
-    // TODO: better error handling
+/// return the filenames of files to be fetched, between a starting and an ending epoch
+pub fn gen_dbin_filenames(
+    start: u64,
+    end: u64,
+    compressed: bool,
+) -> Result<Vec<String>, EraValidateError> {
     if start >= end {
-        panic!("start can't be equal or above end epoch")
-    }
-
-    let mut zst_extension = "";
-    if compressed.unwrap() {
-        zst_extension = ".zst";
+        return Err(EraValidateError::EndEpochLessThanStartEpoch);
     }
 
+    // the blocks of the epochs from `start` up to, and excluding, `end`
     let start_blocks = start * MAX_EPOCH_SIZE as u64;
-    let end_blocks = end * 8200_u64;
+    let end_blocks = end * MAX_EPOCH_SIZE as u64;
     //TODO: count for the FINAL_EPOCH files, which might not be eaxctly 100 blocks named
 
-    for number in (start_blocks..=end_blocks).step_by(100) {
-        let filename = format!("{:010}.dbin{}", number, zst_extension);
-        filenames.push(filename);
+    Ok(gen_dbin_filenames_for_blocks(
+        start_blocks,
+        end_blocks,
+        compressed,
+    ))
+}
+
+/// return the filenames of the bundles holding the blocks between a starting and an ending block,
+/// in the default layout. The interval is exclusive
+pub fn gen_dbin_filenames_for_blocks(start: u64, end: u64, compressed: bool) -> Vec<String> {
+    BundleLayout::default().file_names(start, end, compressed)
+}
+
+/// reads a 32 byte hash stored by firehose, returning its length when it has another one
+pub fn read_hash(bytes: &[u8]) -> Result<B256, usize> {
+    B256::try_from(bytes).map_err(|_| bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dbin_filenames_of_an_epoch() {
+        let file_names = gen_dbin_filenames(0, 1, false).unwrap();
+        assert_eq!(file_names.first().unwrap(), "0000000000.dbin");
+        // block 8191, the last of epoch 0, is in the bundle starting at 8100
+        assert_eq!(file_names.last().unwrap(), "0000008100.dbin");
+        assert_eq!(file_names.len(), 82);
+
+        let file_names = gen_dbin_filenames(1, 2, true).unwrap();
+        assert_eq!(file_names.first().unwrap(), "0000008100.dbin.zst");
+        assert_eq!(file_names.last().unwrap(), "0000016300.dbin.zst");
     }
 
-    filenames
+    #[test]
+    fn empty_epoch_range_is_an_error() {
+        for (start, end) in [(1, 1), (2, 1)] {
+            assert!(matches!(
+                gen_dbin_filenames(start, end, false),
+                Err(EraValidateError::EndEpochLessThanStartEpoch)
+            ));
+        }
+    }
 }
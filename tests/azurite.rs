@@ -0,0 +1,61 @@
+//! Reads bundles from a local Azurite emulator through `az://` URLs.
+//!
+//! Start the emulator and its `flat-files` container with `docker-compose up -d` in `dev/`, then
+//! run `cargo test --test azurite -- --ignored`.
+
+use flat_head::{
+    azure::AzureOptions,
+    inventory::inventory,
+    memory::load_dir,
+    store::{self, StoreOptions},
+};
+use object_store::path::Path;
+
+const CONTAINER: &str = "flat-files";
+
+fn emulator_options() -> StoreOptions {
+    StoreOptions {
+        azure: AzureOptions {
+            use_emulator: Some(true),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs an Azurite emulator, see dev/docker-compose.yml"]
+async fn read_bundles_from_azurite() {
+    let options = emulator_options();
+    let folder = format!("azurite-test-{}", std::process::id());
+
+    let container = options
+        .azure
+        .builder(&format!("az://{}", CONTAINER))
+        .build()
+        .unwrap();
+    let loaded = load_dir(&container, "tests/compressed", &Path::from(folder.as_str()))
+        .await
+        .unwrap();
+    assert_eq!(loaded, 10);
+
+    let store =
+        store::new_with_options(format!("az://{}/{}", CONTAINER, folder), None, &options).unwrap();
+
+    let (file_name, blocks) = store.read_bundle(0).await;
+    assert_eq!(file_name, "0000000000.dbin.zst");
+    let blocks = blocks.unwrap();
+    assert_eq!(blocks.len(), 100);
+    assert!(blocks
+        .iter()
+        .enumerate()
+        .all(|(i, block)| block.number == i as u64));
+
+    let report = inventory(&store, 0, 1000).await.unwrap();
+    assert_eq!(report.found, 10);
+    assert!(report.is_complete());
+
+    // missing bundles are not found rather than failing on the emulator
+    let (_, blocks) = store.read_bundle(1000).await;
+    assert!(blocks.unwrap_err().is_not_found());
+}